@@ -5,9 +5,9 @@
 //!
 //! ## Use an OptVec when:
 //! * You want fast random access and deletion,
-//! but don't want to use expensive structures like HashMap.
+//!   but don't want to use expensive structures like HashMap.
 //! * You want to guarantee that the same index
-//! keeps the same value even if another element is removed.
+//!   keeps the same value even if another element is removed.
 //!
 //! ## Getting Started
 //! Cargo.toml
@@ -37,16 +37,49 @@
 //! default-features = false
 //! features = ["alloc"]
 //! ````
+//!
+//! ## Support `serde`
+//!
+//! Cargo.toml
+//!
+//! ```text
+//! [dependencies.opt_vec]
+//! version = "*"
+//! features = ["serde"]
+//! ```
+//!
+//! An [`OptVec`] is serialized as a sequence of optional values, keeping the free space.
+//! Use `CompactFormat` to serialize only the elements instead.
+//!
+//! ## Sharing between threads
+//!
+//! With the `sync` feature, `SyncOptVec` wraps an [`OptVec`] in a `RwLock`
+//! so that it can be shared between threads.
 #![cfg_attr(not(feature = "std"), no_std)]
 
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+#[cfg(feature = "serde")]
+pub use serde_impl::{CompactFormat, SparseFormat};
+
+#[cfg(feature = "sync")]
+mod sync;
+
+#[cfg(feature = "sync")]
+pub use sync::SyncOptVec;
+
 #[cfg(feature = "alloc")]
-use alloc::vec::Vec;
+use alloc::{borrow::Cow, boxed::Box, collections::BTreeSet, vec::Vec};
 
-use core::convert::identity;
-use core::ops::{Index, IndexMut};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::{Index, IndexMut, Range};
+use core::panic::{RefUnwindSafe, UnwindSafe};
 use core::slice::{Iter, IterMut};
 
 /// A contiguous growable array type with heap-allocated contents
@@ -54,6 +87,9 @@ use core::slice::{Iter, IterMut};
 ///
 /// This is a wrapper for [`Vec<Option<T>>`]
 ///
+/// Comparing with `==` checks the whole structure, including the position of the free space.
+/// To compare only the elements, use [`OptVec::value_eq()`].
+///
 /// ## Examples
 /// ```
 /// use opt_vec::OptVec;
@@ -72,10 +108,190 @@ use core::slice::{Iter, IterMut};
 /// assert_eq!(opt_vec.to_vec(), vec![1, 4, 3]);
 ///
 /// ```
-#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[derive(Default)]
 pub struct OptVec<T> {
     inner: Vec<Option<T>>,
     free: Vec<usize>,
+    reserved: Vec<usize>,
+    policy: ReusePolicy,
+    auto_compact: Option<f64>,
+    on_compact: Option<CompactCallback>,
+    churn: ChurnStats,
+    max_len: Option<usize>,
+}
+
+type CompactCallback = Box<dyn FnMut(&[(usize, usize)]) + Send + Sync + UnwindSafe + RefUnwindSafe>;
+
+/// Decides which free space is filled first by [`OptVec::push()`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ReusePolicy {
+    /// The most recently freed index is reused first
+    Lifo,
+    /// The lowest free index is reused first
+    LowestIndex,
+}
+
+impl Default for ReusePolicy {
+    fn default() -> Self {
+        ReusePolicy::Lifo
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OptVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OptVec")
+            .field("inner", &self.inner)
+            .field("free", &self.free)
+            .field("reserved", &self.reserved)
+            .field("policy", &self.policy)
+            .field("auto_compact", &self.auto_compact)
+            .field("max_len", &self.max_len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: PartialEq> PartialEq for OptVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+            && self.free == other.free
+            && self.reserved == other.reserved
+            && self.policy == other.policy
+    }
+}
+
+impl<T: Eq> Eq for OptVec<T> {}
+
+impl<T: Clone> Clone for OptVec<T> {
+    /// The callback set by [`OptVec::set_compact_callback()`] is not cloned,
+    /// so auto-compaction is disabled on the clone to keep its indices stable.
+    fn clone(&self) -> Self {
+        self.clone_with_capacity(self.inner.len())
+    }
+}
+
+/// Mutable references returned by [`OptVec::get_pair_or_single_mut()`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum PairMut<'a, T> {
+    /// Both indices are the same and the slot has a value
+    Single(&'a mut T),
+    /// The indices are different and both slots have a value
+    Both(&'a mut T, &'a mut T),
+    /// Either index is out of bounds or free space
+    Missing,
+}
+
+/// A snapshot of the memory usage returned by [`OptVec::capacity_report()`]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CapacityReport {
+    /// Same as [`OptVec::len()`]
+    pub live: usize,
+    /// Number of empty slots in the inner vector
+    pub holes: usize,
+    /// Same as [`OptVec::inner_len()`]
+    pub inner_len: usize,
+    /// Capacity of the inner vector
+    pub inner_capacity: usize,
+    /// Capacity of the free list
+    pub free_capacity: usize,
+    /// Same as [`OptVec::fragmentation_ratio()`]
+    pub fragmentation_ratio: f64,
+}
+
+/// The activity counted by [`OptVec::take_churn_stats()`]
+///
+/// Moving elements, e.g. with [`OptVec::compact()`], is not counted,
+/// and neither are the elements of a vector built from existing data, e.g. with [`OptVec::from_parts()`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ChurnStats {
+    /// Number of elements added to free space or to the end,
+    /// e.g. with [`OptVec::push()`] or [`OptVec::insert_at()`]
+    pub pushed: usize,
+    /// Number of elements removed, e.g. with [`OptVec::remove()`], [`OptVec::pop()`] or [`OptVec::retain()`]
+    pub removed: usize,
+}
+
+/// The progress returned by [`OptVec::retain_batched()`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RetainProgress {
+    /// Some slots are left; call again with this cursor to continue
+    Pending { cursor: usize },
+    /// Every slot has been processed
+    Done,
+}
+
+/// The error returned by [`OptVec::try_get()`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccessError {
+    /// The index is out of bounds of the inner vector
+    OutOfBounds { index: usize, inner_len: usize },
+    /// The index points to free space
+    Freed { index: usize },
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::OutOfBounds { index, inner_len } => write!(
+                f,
+                "index {} is out of bounds for inner length {}",
+                index, inner_len
+            ),
+            AccessError::Freed { index } => write!(f, "index {} points to free space", index),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AccessError {}
+
+/// The error returned by [`OptVec::apply_remap()`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RemapError {
+    /// The source index is out of bounds or free space
+    SourceNotLive { index: usize },
+    /// The source index appears more than once
+    DuplicateSource { index: usize },
+    /// The target index appears more than once
+    DuplicateTarget { index: usize },
+    /// The target index has an element that is not moved away, or is reserved
+    TargetOccupied { index: usize },
+}
+
+impl fmt::Display for RemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemapError::SourceNotLive { index } => {
+                write!(f, "source index {} has no element", index)
+            }
+            RemapError::DuplicateSource { index } => {
+                write!(f, "source index {} appears more than once", index)
+            }
+            RemapError::DuplicateTarget { index } => {
+                write!(f, "target index {} appears more than once", index)
+            }
+            RemapError::TargetOccupied { index } => write!(f, "target index {} is occupied", index),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RemapError {}
+
+/// A violation of the invariants found by [`OptVec::validate()`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Inconsistency {
+    /// The free list has an index that is out of bounds
+    FreeOutOfBounds { index: usize },
+    /// The free list has an index whose slot has an element
+    FreeOccupied { index: usize },
+    /// The free list has the same index more than once
+    DuplicateFree { index: usize },
+    /// An empty slot is neither in the free list nor reserved
+    MissingFree { index: usize },
+    /// A reserved slot is out of bounds, has an element, is in the free list or is reserved twice
+    ReservedInvalid { index: usize },
+    /// The free list is not sorted as required by [`ReusePolicy::LowestIndex`]
+    FreeUnsorted,
 }
 
 impl<T> OptVec<T> {
@@ -85,6 +301,11 @@ impl<T> OptVec<T> {
         self.inner.iter().filter(|a| a.is_some()).count()
     }
 
+    /// Returns `true` if the vector contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inner.iter().all(|a| a.is_none())
+    }
+
     /// Returns the number of elements in the inner vector, also referred to as its `length`
     pub fn inner_len(&self) -> usize {
         self.inner.len()
@@ -101,7 +322,7 @@ impl<T> OptVec<T> {
 
     /// Converts the [`OptVec<T>`] into [`Vec<T>`]
     pub fn to_vec(self) -> Vec<T> {
-        self.inner.into_iter().filter_map(|v| v).collect()
+        self.inner.into_iter().flatten().collect()
     }
 
     /// Constructs a new, empty `OptVec<T>`
@@ -110,6 +331,12 @@ impl<T> OptVec<T> {
         Self {
             inner: Vec::new(),
             free: Vec::new(),
+            reserved: Vec::new(),
+            policy: ReusePolicy::Lifo,
+            auto_compact: None,
+            on_compact: None,
+            churn: ChurnStats::default(),
+            max_len: None,
         }
     }
 
@@ -119,19 +346,54 @@ impl<T> OptVec<T> {
         Self {
             inner: Vec::with_capacity(capacity),
             free: Vec::with_capacity(capacity),
+            ..Self::new()
         }
     }
 
     /// Removes the last element from a vector and returns it, or [`None`] if it
     /// is empty.
+    /// Free space left at the end of the inner vector is removed as with [`OptVec::reclaim()`].
     pub fn pop(&mut self) -> Option<T> {
-        self.inner.pop().and_then(identity)
+        let i = self.inner.iter().rposition(Option::is_some)?;
+        self.churn.removed += 1;
+        let value = if i + 1 == self.inner.len() {
+            self.inner.pop().flatten()
+        } else {
+            self.add_free(i);
+            self.inner[i].take()
+        };
+        if self.inner.last().map_or(false, Option::is_none) {
+            self.reclaim();
+        }
+        self.auto_compact_check();
+        value
+    }
+
+    /// Returns a reference to the element that [`OptVec::pop()`] would remove,
+    /// or [`None`] if it is empty.
+    pub fn peek_last(&self) -> Option<&T> {
+        self.inner.iter().rev().flatten().next()
+    }
+
+    /// Returns the policy deciding which free space is filled first.
+    pub fn reuse_policy(&self) -> ReusePolicy {
+        self.policy
+    }
+
+    /// Sets the policy deciding which free space is filled first.
+    pub fn set_reuse_policy(&mut self, policy: ReusePolicy) {
+        if policy == ReusePolicy::LowestIndex && self.policy != policy {
+            self.normalize_free_list();
+        }
+        self.policy = policy;
     }
 
     /// Appends an element to the first free space.
     /// ## Panic
     /// Panics if the new capacity exceeds isize::MAX bytes.
     pub fn push(&mut self, value: T) -> usize {
+        self.auto_compact_check();
+        self.churn.pushed += 1;
         if let Some(i) = self.free.pop() {
             self.inner[i] = Some(value);
             i
@@ -143,84 +405,3721 @@ impl<T> OptVec<T> {
 
     /// Removes and returns the element at the position index within the vector.
     pub fn remove(&mut self, index: usize) -> Option<T> {
-        if self.inner[index].is_some() {
-            self.free.push(index);
-            self.inner[index].take()
+        let value = self.take(index);
+        self.auto_compact_check();
+        value
+    }
+
+    /// Returns a new [`OptVec<T>`] containing clones of the elements for which `f` returns `true`.
+    /// Each cloned element keeps its index; every other slot becomes free space.
+    pub fn filter_to_new<F: FnMut(&T) -> bool>(&self, mut f: F) -> OptVec<T>
+    where
+        T: Clone,
+    {
+        self.derived(
+            self.inner
+                .iter()
+                .map(|a| a.as_ref().filter(|a| f(a)).cloned())
+                .collect(),
+        )
+    }
+
+    /// Returns the number of elements whose index lies within `range`.
+    /// The range is clamped to [`OptVec::inner_len()`].
+    pub fn count_live_in_range(&self, range: Range<usize>) -> usize {
+        let end = range.end.min(self.inner.len());
+        let start = range.start.min(end);
+        self.inner[start..end]
+            .iter()
+            .filter(|a| a.is_some())
+            .count()
+    }
+
+    /// Appends `count` default values, filling free space first.
+    pub fn fill_default(&mut self, count: usize)
+    where
+        T: Default,
+    {
+        for _ in 0..count {
+            self.push(T::default());
+        }
+    }
+
+    /// Returns the highest index of the free space, or [`None`] if there is no free space.
+    /// This takes `O(n)` time in the length of the free list.
+    pub fn highest_free_index(&self) -> Option<usize> {
+        self.free.iter().max().copied()
+    }
+
+    /// Moves all elements to the front of the inner vector, keeping their order,
+    /// and removes the free space.
+    /// Returns `(old_index, new_index)` for each element that was moved.
+    pub fn compact(&mut self) -> Vec<(usize, usize)> {
+        let mut remap = Vec::new();
+        self.compact_with(|old, new| remap.push((old, new)));
+        remap
+    }
+
+    /// Same as [`OptVec::compact()`], but calls `on_move(old_index, new_index)`
+    /// for each element that was moved instead of collecting them.
+    pub fn compact_with<F: FnMut(usize, usize)>(&mut self, mut on_move: F) {
+        let mut next = 0;
+        for i in 0..self.inner.len() {
+            if self.inner[i].is_some() {
+                if i != next {
+                    self.inner.swap(i, next);
+                    on_move(i, next);
+                }
+                next += 1;
+            }
+        }
+        self.inner.truncate(next);
+        self.free.clear();
+        self.reserved.clear();
+    }
+
+    /// Keeps the first `n` elements in `self` and moves the rest into a new [`OptVec<T>`].
+    /// The moved elements are re-based, so they are stored from index `0` without free space.
+    pub fn split_at_ordinal(&mut self, n: usize) -> OptVec<T> {
+        let at = if n == 0 {
+            0
+        } else {
+            match self
+                .inner
+                .iter()
+                .enumerate()
+                .filter(|(_, a)| a.is_some())
+                .nth(n - 1)
+            {
+                Some((i, _)) => i + 1,
+                None => return self.derived(Vec::new()),
+            }
+        };
+        let tail = self.inner.split_off(at);
+        self.free.retain(|&i| i < at);
+        self.reserved.retain(|&i| i < at);
+        let tail: Vec<Option<T>> = tail.into_iter().filter(|a| a.is_some()).collect();
+        self.churn.removed += tail.len();
+        self.derived(tail)
+    }
+
+    /// Removes the free space at the end of the inner vector and returns how many slots were removed.
+    /// Unlike [`Vec::shrink_to_fit()`], the allocated memory is kept for reuse.
+    pub fn reclaim(&mut self) -> usize {
+        let trailing = self.trailing_free();
+        let new_len = self.inner.len() - trailing;
+        if trailing > 0 {
+            self.inner.truncate(new_len);
+            match self.policy {
+                ReusePolicy::Lifo => self.free.retain(|&i| i < new_len),
+                ReusePolicy::LowestIndex => {
+                    let p = self.free.partition_point(|&i| i >= new_len);
+                    self.free.drain(..p);
+                }
+            }
+        }
+        trailing
+    }
+
+    /// Returns mutable references to the elements at `a` and `b`.
+    /// If `a == b`, a single reference is returned instead of failing.
+    pub fn get_pair_or_single_mut(&mut self, a: usize, b: usize) -> PairMut<'_, T> {
+        if a == b {
+            return match self.inner.get_mut(a) {
+                Some(Some(x)) => PairMut::Single(x),
+                _ => PairMut::Missing,
+            };
+        }
+        let (lo, hi) = if a < b { (a, b) } else { (b, a) };
+        if hi >= self.inner.len() {
+            return PairMut::Missing;
+        }
+        let (left, right) = self.inner.split_at_mut(hi);
+        match (left[lo].as_mut(), right[0].as_mut()) {
+            (Some(x), Some(y)) if a < b => PairMut::Both(x, y),
+            (Some(x), Some(y)) => PairMut::Both(y, x),
+            _ => PairMut::Missing,
+        }
+    }
+
+    /// Inserts an element at position `index` and returns the previous element, if any.
+    /// If `index` is out of bounds, the inner vector is padded with free space.
+    pub fn insert_at(&mut self, index: usize, value: T) -> Option<T> {
+        let previous = self.place(index, value);
+        if previous.is_none() {
+            self.churn.pushed += 1;
+        }
+        previous
+    }
+
+    /// Same as [`OptVec::insert_at()`], but does not count the insertion
+    /// for [`OptVec::take_churn_stats()`], as the element is only moved.
+    fn place(&mut self, index: usize, value: T) -> Option<T> {
+        if index >= self.inner.len() {
+            self.grow_to(index);
+            self.inner.push(Some(value));
+            return None;
+        }
+        if self.inner[index].is_none() && !self.unreserve(index) {
+            self.unfree(index);
+        }
+        self.inner[index].replace(value)
+    }
+
+    /// Removes all but the first of consecutive elements that resolve to the same key.
+    /// Free space between elements is ignored, and the kept elements keep their index.
+    pub fn dedup_by_key<K: PartialEq, F: FnMut(&T) -> K>(&mut self, mut key: F) {
+        let mut last = None;
+        for i in 0..self.inner.len() {
+            if let Some(a) = &self.inner[i] {
+                let k = key(a);
+                if last.as_ref() == Some(&k) {
+                    self.take(i);
+                } else {
+                    last = Some(k);
+                }
+            }
+        }
+    }
+
+    /// Returns the elements as a slice in index order, without free space.
+    /// Since the elements are stored as [`Option<T>`], this always clones them.
+    pub fn live_slice_view(&self) -> Cow<'_, [T]>
+    where
+        T: Clone,
+    {
+        let mut values = Vec::with_capacity(self.len());
+        values.extend(self.inner.iter().flatten().cloned());
+        Cow::Owned(values)
+    }
+
+    /// Removes the element at position `index` and returns `true` if there was one.
+    /// Unlike [`OptVec::remove()`], this does not panic if `index` is out of bounds.
+    pub fn remove_if_present(&mut self, index: usize) -> bool {
+        match self.inner.get_mut(index) {
+            Some(a @ Some(_)) => {
+                *a = None;
+                self.free_index(index);
+                self.auto_compact_check();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves the elements for which `f` returns `true` in front of the others,
+    /// keeping the relative order within each group, and removes the free space.
+    /// Returns the number of elements for which `f` returned `true`.
+    ///
+    /// Note that this changes the index of the elements.
+    pub fn stable_partition<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> usize {
+        let mut rest = Vec::new();
+        let mut next = 0;
+        for i in 0..self.inner.len() {
+            if let Some(a) = self.inner[i].take() {
+                if f(&a) {
+                    self.inner[next] = Some(a);
+                    next += 1;
+                } else {
+                    rest.push(a);
+                }
+            }
+        }
+        self.inner.truncate(next);
+        self.inner.extend(rest.into_iter().map(Some));
+        self.free.clear();
+        self.reserved.clear();
+        next
+    }
+
+    /// Moves all elements of `other` into `self`, filling free space first.
+    /// Returns `(other_index, self_index)` for each moved element.
+    pub fn merge_fill(&mut self, other: OptVec<T>) -> Vec<(usize, usize)> {
+        other
+            .inner
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, a)| a.map(|a| (i, self.push(a))))
+            .collect()
+    }
+
+    /// Returns `true` if both vectors contain the same elements in the same order,
+    /// regardless of the position of the free space.
+    pub fn value_eq(&self, other: &OptVec<T>) -> bool
+    where
+        T: PartialEq,
+    {
+        self.inner.iter().flatten().eq(other.inner.iter().flatten())
+    }
+
+    /// Feeds the elements in index order into `state`, ignoring the free space.
+    /// Vectors that are equal by [`OptVec::value_eq()`] produce the same hash.
+    pub fn hash_values<H: Hasher>(&self, state: &mut H)
+    where
+        T: Hash,
+    {
+        state.write_usize(self.len());
+        for a in self.inner.iter().flatten() {
+            a.hash(state);
+        }
+    }
+
+    /// Returns a reference to the element at position `index`,
+    /// or [`None`] if it is out of bounds or free space.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.inner.get(index).and_then(Option::as_ref)
+    }
+
+    /// Returns a mutable reference to the element at position `index`,
+    /// or [`None`] if it is out of bounds or free space.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.inner.get_mut(index).and_then(Option::as_mut)
+    }
+
+    /// Returns a reference to the element at position `index`,
+    /// or an [`AccessError`] describing why there is none.
+    pub fn try_get(&self, index: usize) -> Result<&T, AccessError> {
+        match self.inner.get(index) {
+            Some(Some(a)) => Ok(a),
+            Some(None) => Err(AccessError::Freed { index }),
+            None => Err(AccessError::OutOfBounds {
+                index,
+                inner_len: self.inner.len(),
+            }),
+        }
+    }
+
+    /// Replaces each element with the result of `f`, removing it if `f` returns [`None`].
+    /// The remaining elements keep their index.
+    pub fn retain_map<F: FnMut(T) -> Option<T>>(&mut self, mut f: F) {
+        for i in 0..self.inner.len() {
+            if let Some(a) = self.inner[i].take() {
+                self.inner[i] = f(a);
+                if self.inner[i].is_none() {
+                    self.free_index(i);
+                }
+            }
+        }
+    }
+
+    /// Returns how many elements can be pushed before the inner vector reallocates.
+    /// Calculated by the following formula:
+    ///
+    /// `free space length + (inner vector capacity - inner vector length)`
+    ///
+    pub fn capacity_remaining(&self) -> usize {
+        self.free.len() + (self.inner.capacity() - self.inner.len())
+    }
+
+    /// Returns the ratio of free space to the length of the inner vector, from `0.0` to `1.0`.
+    /// Returns `0.0` if the inner vector is empty.
+    pub fn fragmentation_ratio(&self) -> f64 {
+        if self.inner.is_empty() {
+            0.0
+        } else {
+            (self.inner.len() - self.len()) as f64 / self.inner.len() as f64
+        }
+    }
+
+    /// Runs [`OptVec::compact()`] only if `cond` returns `true`,
+    /// e.g. `v.compact_if(|v| v.fragmentation_ratio() > 0.5)`.
+    pub fn compact_if<F: FnOnce(&Self) -> bool>(&mut self, cond: F) -> Option<Vec<(usize, usize)>> {
+        if cond(self) {
+            Some(self.compact())
         } else {
             None
         }
     }
-}
 
-impl<T> Index<usize> for OptVec<T> {
-    type Output = T;
+    /// Calls `f` with mutable references to every unordered pair of elements and their index.
+    /// The first element of each pair always has the lower index.
+    pub fn for_each_pair_mut<F: FnMut((usize, &mut T), (usize, &mut T))>(&mut self, mut f: F) {
+        for j in 1..self.inner.len() {
+            let (left, right) = self.inner.split_at_mut(j);
+            if let Some(b) = right[0].as_mut() {
+                for (i, a) in left.iter_mut().enumerate() {
+                    if let Some(a) = a.as_mut() {
+                        f((i, a), (j, b));
+                    }
+                }
+            }
+        }
+    }
 
-    fn index(&self, index: usize) -> &Self::Output {
-        self.inner[index].as_ref().unwrap()
+    /// Removes the free space at the end of the inner vector, rebuilds the free list
+    /// and shrinks the capacity as much as possible.
+    /// No element is moved, so every element keeps its index.
+    pub fn shrink_holes_only(&mut self) {
+        let len = self.inner.len() - self.trailing_free();
+        self.inner.truncate(len);
+        self.inner.shrink_to_fit();
+        self.rebuild_free_list();
+        self.free.shrink_to_fit();
     }
-}
 
-impl<T> IndexMut<usize> for OptVec<T> {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        self.inner[index].as_mut().unwrap()
+    /// Replaces the element at position `index` with its default value, keeping the slot in use.
+    /// Does nothing if `index` is out of bounds or free space.
+    pub fn reset(&mut self, index: usize)
+    where
+        T: Default,
+    {
+        if let Some(a) = self.get_mut(index) {
+            *a = T::default();
+        }
     }
-}
 
-impl<'a, T> IntoIterator for &'a OptVec<T> {
-    type Item = &'a Option<T>;
+    /// Removes the elements whose index lies within `range` and returns them with their index.
+    /// The range is clamped to [`OptVec::inner_len()`].
+    ///
+    /// Elements are removed as the iterator advances,
+    /// so dropping it early leaves the remaining elements in place.
+    pub fn drain_range(&mut self, range: Range<usize>) -> impl Iterator<Item = (usize, T)> + '_ {
+        let end = range.end.min(self.inner.len());
+        let start = range.start.min(end);
+        (start..end).filter_map(move |i| {
+            let a = self.inner[i].take()?;
+            self.free_index(i);
+            Some((i, a))
+        })
+    }
 
-    type IntoIter = Iter<'a, Option<T>>;
+    /// Constructs an [`OptVec<T>`] from an inner vector and a free list.
+    ///
+    /// The parts are not checked: every index in `free` must point to a [`None`]
+    /// in `inner`, and every [`None`] should be in `free`. If unsure, call
+    /// [`OptVec::rebuild_free_list()`] afterwards.
+    pub fn from_parts(inner: Vec<Option<T>>, free: Vec<usize>) -> Self {
+        Self {
+            inner,
+            free,
+            ..Self::new()
+        }
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.inner.iter()
+    /// Converts the [`OptVec<T>`] into its inner vector, keeping the free space as [`None`].
+    pub fn into_vec_with_holes(self) -> Vec<Option<T>> {
+        self.inner
     }
-}
 
-impl<'a, T> IntoIterator for &'a mut OptVec<T> {
-    type Item = &'a mut Option<T>;
+    /// Removes every element whose index is not in `keep`.
+    /// Indices in `keep` that are out of bounds or free space are ignored.
+    pub fn keep_only(&mut self, keep: &BTreeSet<usize>) {
+        for i in 0..self.inner.len() {
+            if !keep.contains(&i) {
+                self.take(i);
+            }
+        }
+    }
 
-    type IntoIter = IterMut<'a, Option<T>>;
+    /// Extends the inner vector with free space until its length is `inner_len`.
+    /// Does nothing if the inner vector is already that long.
+    pub fn grow_to(&mut self, inner_len: usize) {
+        let len = self.inner.len();
+        if inner_len <= len {
+            return;
+        }
+        self.inner.resize_with(inner_len, || None);
+        match self.policy {
+            ReusePolicy::Lifo => self.free.extend((len..inner_len).rev()),
+            ReusePolicy::LowestIndex => {
+                self.free.splice(0..0, (len..inner_len).rev());
+            }
+        }
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.inner.iter_mut()
+    /// Calls `f` with each element and its index in index order,
+    /// and returns the first non-[`None`] result.
+    pub fn find_map<U, F: FnMut(usize, &T) -> Option<U>>(&self, mut f: F) -> Option<U> {
+        self.inner
+            .iter()
+            .enumerate()
+            .find_map(|(i, a)| a.as_ref().and_then(|a| f(i, a)))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::OptVec;
+    /// Reserves capacity for at least `additional` more entries in the free list,
+    /// so that removing that many elements does not reallocate it.
+    pub fn reserve_free(&mut self, additional: usize) {
+        self.free.reserve(additional);
+    }
 
-    #[test]
-    fn ok() {
-        let mut v: OptVec<i32> = OptVec::new();
-        assert_eq!(v.push(1), 0);
-        assert_eq!(v.push(2), 1);
-        assert_eq!(v.push(3), 2);
-        assert_eq!(v.push(4), 3);
-        assert_eq!(v.push(5), 4);
+    /// Reserves capacity for exactly `additional` more entries in the free list.
+    /// For a detailed explanation, see [here](https://doc.rust-lang.org/std/vec/struct.Vec.html#method.reserve_exact)
+    pub fn reserve_free_exact(&mut self, additional: usize) {
+        self.free.reserve_exact(additional);
+    }
 
-        assert_eq!(v.pop(), Some(5));
-        assert_eq!(v.inner, vec![Some(1), Some(2), Some(3), Some(4)]);
-        assert_eq!(v.free, vec![] as Vec<usize>);
+    /// Removes every element and returns them with their index.
+    /// Unlike [`OptVec::to_vec()`], the inner vector keeps its length,
+    /// so the slots can be filled again at the same indices.
+    ///
+    /// Elements are removed as the iterator advances,
+    /// so dropping it early leaves the remaining elements in place.
+    pub fn take_all(&mut self) -> impl Iterator<Item = (usize, T)> + '_ {
+        let len = self.inner.len();
+        self.drain_range(0..len)
+    }
 
-        assert_eq!(v.remove(1), Some(2));
-        assert_eq!(v.inner, vec![Some(1), None, Some(3), Some(4)]);
-        assert_eq!(v.free, vec![1]);
+    /// Same as [`OptVec::compact()`], but returns a function that maps an old index
+    /// to its new index, or to [`None`] if there was no element at the old index.
+    pub fn compact_stable(&mut self) -> impl Fn(usize) -> Option<usize> {
+        let mut map: Vec<Option<usize>> = (0..self.inner.len())
+            .map(|i| self.inner[i].as_ref().map(|_| i))
+            .collect();
+        self.compact_with(|old, new| map[old] = Some(new));
+        move |i| map.get(i).copied().flatten()
+    }
 
-        assert_eq!(v.remove(1), None);
-        assert_eq!(v.inner, vec![Some(1), None, Some(3), Some(4)]);
-        assert_eq!(v.free, vec![1]);
+    /// Removes the element with the lowest index and returns it with its index,
+    /// or [`None`] if it is empty.
+    pub fn pop_front(&mut self) -> Option<(usize, T)> {
+        let i = self.inner.iter().position(|a| a.is_some())?;
+        self.remove(i).map(|a| (i, a))
+    }
 
-        assert_eq!(v.push(5), 1);
-        assert_eq!(v.inner, vec![Some(1), Some(5), Some(3), Some(4)]);
-        assert_eq!(v.free, vec![] as Vec<usize>);
+    /// Returns a new [`OptVec<V>`] with the result of `f` at each index where both `self`
+    /// and `other` have an element. Every other slot becomes free space.
+    pub fn zip_with<U, V, F: FnMut(&T, &U) -> V>(&self, other: &OptVec<U>, mut f: F) -> OptVec<V> {
+        self.derived(
+            self.inner
+                .iter()
+                .zip(&other.inner)
+                .map(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) => Some(f(a, b)),
+                    _ => None,
+                })
+                .collect(),
+        )
     }
 
-    #[should_panic]
-    #[test]
-    fn err() {
-        let mut v = OptVec::new();
-        v.push(1);
-        v.remove(1);
+    /// Returns mutable references to the elements at each of the `indices`,
+    /// or [`None`] if any index is duplicated, out of bounds or free space.
+    pub fn get_disjoint_mut<const N: usize>(&mut self, indices: [usize; N]) -> Option<[&mut T; N]> {
+        let len = self.inner.len();
+        if indices.iter().any(|&i| i >= len) {
+            return None;
+        }
+        let mut order = [0; N];
+        for (k, o) in order.iter_mut().enumerate() {
+            *o = k;
+        }
+        order.sort_unstable_by_key(|&k| indices[k]);
+        if order.windows(2).any(|w| indices[w[0]] == indices[w[1]]) {
+            return None;
+        }
+        let mut refs = [(); N].map(|_| None);
+        let mut rest = &mut self.inner[..];
+        let mut offset = 0;
+        for &k in &order {
+            let (_, tail) = core::mem::take(&mut rest).split_at_mut(indices[k] - offset);
+            let (a, tail) = tail.split_first_mut()?;
+            refs[k] = Some(a.as_mut()?);
+            rest = tail;
+            offset = indices[k] + 1;
+        }
+        Some(refs.map(Option::unwrap))
+    }
+
+    /// Returns a bitmap where bit `i % 64` of word `i / 64` is set
+    /// if there is an element at index `i`.
+    pub fn occupancy_bitmap(&self) -> Vec<u64> {
+        let mut bitmap = alloc::vec![0; (self.inner.len() + 63) / 64];
+        for (i, a) in self.inner.iter().enumerate() {
+            if a.is_some() {
+                bitmap[i / 64] |= 1 << (i % 64);
+            }
+        }
+        bitmap
+    }
+
+    /// Constructs an [`OptVec<T>`] from a bitmap as returned by [`OptVec::occupancy_bitmap()`],
+    /// placing the next value from `values` at each set bit in ascending order.
+    /// Cleared bits become free space, and the inner vector ends at the highest set bit.
+    /// ## Panic
+    /// Panics if `values` has fewer items than there are set bits.
+    pub fn from_occupancy(bitmap: &[u64], mut values: impl Iterator<Item = T>) -> Self {
+        let len = bitmap
+            .iter()
+            .rposition(|&w| w != 0)
+            .map_or(0, |k| k * 64 + 64 - bitmap[k].leading_zeros() as usize);
+        let inner = (0..len)
+            .map(|i| {
+                if bitmap[i / 64] & (1 << (i % 64)) != 0 {
+                    Some(values.next().expect("not enough values for the bitmap"))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let mut v = Self::from_parts(inner, Vec::new());
+        v.rebuild_free_list();
+        v
+    }
+
+    /// Returns an iterator over the elements and their index, starting at index `start`.
+    pub fn iter_from(&self, start: usize) -> impl Iterator<Item = (usize, &T)> {
+        self.inner
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter_map(|(i, a)| a.as_ref().map(|a| (i, a)))
+    }
+
+    /// Retains only the elements for which `f` returns `true`.
+    /// The remaining elements keep their index.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        self.retain_count(f);
+    }
+
+    /// Same as [`OptVec::retain()`], but returns the number of removed elements.
+    pub fn retain_count<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> usize {
+        let mut removed = 0;
+        for i in 0..self.inner.len() {
+            if matches!(&self.inner[i], Some(a) if !f(a)) {
+                self.inner[i] = None;
+                self.free_index(i);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Returns a guard for setting many slots at once.
+    /// The free list is only rebuilt once, when the guard is dropped.
+    pub fn edit(&mut self) -> EditGuard<'_, T> {
+        EditGuard { vec: self }
+    }
+
+    /// Reverses the order of the elements, keeping the position of the free space.
+    pub fn reverse(&mut self) {
+        let (mut i, mut j) = (0, self.inner.len());
+        loop {
+            while i < j && self.inner[i].is_none() {
+                i += 1;
+            }
+            while i < j && self.inner[j - 1].is_none() {
+                j -= 1;
+            }
+            if j - i < 2 {
+                break;
+            }
+            self.inner.swap(i, j - 1);
+            i += 1;
+            j -= 1;
+        }
+    }
+
+    /// Rotates the elements `n` places to the left, keeping the position of the free space.
+    /// The first element moves to the slot of the last one when `n == 1`.
+    pub fn rotate_left(&mut self, n: usize) {
+        self.rotate_with(|values| {
+            let n = n % values.len();
+            values.rotate_left(n)
+        });
+    }
+
+    /// Rotates the elements `n` places to the right, keeping the position of the free space.
+    /// The last element moves to the slot of the first one when `n == 1`.
+    pub fn rotate_right(&mut self, n: usize) {
+        self.rotate_with(|values| {
+            let n = n % values.len();
+            values.rotate_right(n)
+        });
+    }
+
+    /// Returns the minimum element and its index, or [`None`] if it is empty.
+    /// If several elements are equally minimum, the one with the lowest index is returned.
+    pub fn min(&self) -> Option<(usize, &T)>
+    where
+        T: Ord,
+    {
+        self.iter_from(0).min_by(|a, b| a.1.cmp(b.1))
+    }
+
+    /// Returns the maximum element and its index, or [`None`] if it is empty.
+    /// If several elements are equally maximum, the one with the lowest index is returned.
+    pub fn max(&self) -> Option<(usize, &T)>
+    where
+        T: Ord,
+    {
+        self.iter_from(0)
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+    }
+
+    /// Folds every element into an accumulator in index order, skipping the free space.
+    pub fn fold<B, F: FnMut(B, &T) -> B>(&self, init: B, f: F) -> B {
+        self.inner.iter().flatten().fold(init, f)
+    }
+
+    /// Removes every element that is equal to an element with a lower index.
+    /// Unlike [`OptVec::dedup_by_key()`], the duplicates do not have to be consecutive.
+    #[cfg(feature = "std")]
+    pub fn dedup_global(&mut self)
+    where
+        T: Eq + Hash,
+    {
+        let mut seen = std::collections::HashSet::new();
+        let duplicates: Vec<usize> = self
+            .iter_from(0)
+            .filter(|&(_, a)| !seen.insert(a))
+            .map(|(i, _)| i)
+            .collect();
+        for i in duplicates {
+            self.take(i);
+        }
+    }
+
+    /// Returns a recommended capacity for [`OptVec::with_capacity()`], given the expected number
+    /// of elements and how many of them are expected to be removed while the others are alive.
+    ///
+    /// Removed elements leave free space that is only reused by later pushes,
+    /// so the inner vector needs room for both.
+    pub fn capacity_for(expected_live: usize, expected_churn: usize) -> usize {
+        expected_live.saturating_add(expected_churn)
+    }
+
+    /// Converts the [`OptVec<T>`] into an iterator over every slot, keeping the free space as [`None`].
+    pub fn into_iter_with_holes(self) -> alloc::vec::IntoIter<Option<T>> {
+        self.inner.into_iter()
+    }
+
+    /// Removes free space at the end of the inner vector until the free list has at most
+    /// `max_free` entries, or there is no more free space at the end.
+    /// No element is moved, so every element keeps its index.
+    pub fn trim_free_to(&mut self, max_free: usize) {
+        let excess = self.free.len().saturating_sub(max_free);
+        let new_len = self.inner.len() - excess.min(self.trailing_free());
+        if new_len < self.inner.len() {
+            self.inner.truncate(new_len);
+            self.free.retain(|&i| i < new_len);
+        }
+    }
+
+    /// Reserves a slot and returns its index, so that the index is known before the value is.
+    /// The slot stays empty and is not filled by [`OptVec::push()`] until a value is put there
+    /// with [`OptVec::insert_at()`].
+    ///
+    /// Operations that move elements, such as [`OptVec::compact()`], drop outstanding reservations.
+    pub fn allocate_slot(&mut self) -> usize {
+        let index = match self.free.pop() {
+            Some(i) => i,
+            None => {
+                self.inner.push(None);
+                self.inner.len() - 1
+            }
+        };
+        self.reserved.push(index);
+        index
+    }
+
+    /// Returns a slot reserved by [`OptVec::allocate_slot()`] to the free list.
+    /// If the slot has a value, the value is removed as with [`OptVec::remove()`].
+    /// Does nothing if `index` is out of bounds or already free space.
+    pub fn release_slot(&mut self, index: usize) {
+        if self.unreserve(index) {
+            self.add_free(index);
+        } else {
+            self.remove_if_present(index);
+        }
+    }
+
+    /// Same as [`OptVec::retain()`], but moves the removed elements into `sink` in index order.
+    pub fn retain_draining_into<F: FnMut(&T) -> bool>(&mut self, mut f: F, sink: &mut Vec<T>) {
+        for i in 0..self.inner.len() {
+            if matches!(&self.inner[i], Some(a) if !f(a)) {
+                sink.extend(self.inner[i].take());
+                self.free_index(i);
+            }
+        }
+    }
+
+    /// Inserts each value at consecutive indices from `start`, as with [`OptVec::insert_at()`].
+    /// Elements already in that range are overwritten.
+    pub fn bulk_insert_at(&mut self, start: usize, values: impl IntoIterator<Item = T>) {
+        for (i, value) in values.into_iter().enumerate() {
+            self.insert_at(start + i, value);
+        }
+    }
+
+    /// Counts the elements by the key returned by `key`.
+    #[cfg(feature = "std")]
+    pub fn count_by<K: Eq + Hash, F: FnMut(&T) -> K>(
+        &self,
+        mut key: F,
+    ) -> std::collections::HashMap<K, usize> {
+        let mut counts = std::collections::HashMap::new();
+        for a in self.inner.iter().flatten() {
+            *counts.entry(key(a)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Removes every element for which `f` returns `true` and returns them.
+    /// Each emptied slot is filled with the element of the highest index, as with
+    /// [`Vec::swap_remove()`], so that no new free space is left behind.
+    ///
+    /// Note that this changes the index of the moved elements.
+    pub fn swap_remove_matching<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> Vec<T> {
+        let mut removed = Vec::new();
+        let mut end = self.inner.len();
+        let mut i = 0;
+        while i < end {
+            if matches!(&self.inner[i], Some(a) if f(a)) {
+                removed.extend(self.inner[i].take());
+                self.churn.removed += 1;
+                while end > i + 1 {
+                    end -= 1;
+                    match self.inner[end].take() {
+                        Some(b) if f(&b) => {
+                            removed.push(b);
+                            self.churn.removed += 1;
+                        }
+                        Some(b) => {
+                            self.inner[i] = Some(b);
+                            break;
+                        }
+                        None => {}
+                    }
+                }
+                if self.inner[i].is_none() {
+                    end = i;
+                }
+            }
+            i += 1;
+        }
+        self.inner.truncate(end);
+        self.free.retain(|&i| i < end);
+        self.reserved.retain(|&i| i < end);
+        removed
+    }
+
+    /// Returns `true` if there is no free space, i.e. `len() == inner_len()`.
+    /// This takes `O(1)` time.
+    pub fn is_dense(&self) -> bool {
+        self.free.is_empty() && self.reserved.is_empty()
+    }
+
+    /// Returns references to all elements in index order if there is no free space,
+    /// or [`None`] otherwise.
+    ///
+    /// The elements are stored as [`Option<T>`], so they cannot be borrowed as `&[T]`;
+    /// the references are collected instead.
+    pub fn as_dense_slice(&self) -> Option<Vec<&T>> {
+        if self.is_dense() {
+            Some(self.inner.iter().flatten().collect())
+        } else {
+            None
+        }
+    }
+
+    /// Pushes a clone of each item, filling free space first, and returns their indices.
+    pub fn append_slice(&mut self, items: &[T]) -> Vec<usize>
+    where
+        T: Clone,
+    {
+        items.iter().map(|a| self.push(a.clone())).collect()
+    }
+
+    /// Same as [`OptVec::compact()`], but also sorts the elements with `compare`.
+    /// The sort is stable, and the returned `(old_index, new_index)` only contains
+    /// the elements that were moved.
+    pub fn compact_sorted_by<F: FnMut(&T, &T) -> Ordering>(
+        &mut self,
+        mut compare: F,
+    ) -> Vec<(usize, usize)> {
+        let mut values: Vec<(usize, T)> = self
+            .inner
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, a)| a.take().map(|a| (i, a)))
+            .collect();
+        values.sort_by(|a, b| compare(&a.1, &b.1));
+        self.inner.clear();
+        self.free.clear();
+        self.reserved.clear();
+        let mut remap = Vec::new();
+        for (new, (old, a)) in values.into_iter().enumerate() {
+            if old != new {
+                remap.push((old, new));
+            }
+            self.inner.push(Some(a));
+        }
+        remap
+    }
+
+    /// Overwrites `target` with a clone of `self`, reusing the allocations of `target`.
+    /// Auto-compaction is disabled on `target`, as with [`Clone`].
+    pub fn clone_into(&self, target: &mut OptVec<T>)
+    where
+        T: Clone,
+    {
+        target.inner.clone_from(&self.inner);
+        target.free.clone_from(&self.free);
+        target.reserved.clone_from(&self.reserved);
+        target.policy = self.policy;
+        target.auto_compact = None;
+        target.on_compact = None;
+        target.churn = ChurnStats::default();
+        target.max_len = self.max_len;
+    }
+
+    /// Returns an iterator over all windows of `size` consecutive elements, skipping the free space.
+    /// ## Panic
+    /// Panics if `size` is 0.
+    pub fn windows_live(&self, size: usize) -> impl Iterator<Item = Vec<&T>> {
+        assert!(size != 0, "window size must be non-zero");
+        let values: Vec<&T> = self.inner.iter().flatten().collect();
+        let count = (values.len() + 1).saturating_sub(size);
+        (0..count).map(move |i| values[i..i + size].to_vec())
+    }
+
+    /// Reserves capacity so that a following [`OptVec::insert_at()`] with `index`
+    /// does not reallocate, including the free list entries for the padding.
+    pub fn reserve_for_insert_at(&mut self, index: usize) {
+        let len = self.inner.len();
+        if index >= len {
+            self.inner.reserve(index + 1 - len);
+            self.free.reserve(index - len);
+        }
+    }
+
+    /// Removes the elements whose index lies within `range`, then runs [`OptVec::compact()`].
+    /// Returns the removed elements in index order.
+    ///
+    /// Note that this changes the index of the remaining elements.
+    pub fn remove_range_compact(&mut self, range: Range<usize>) -> Vec<T> {
+        let removed = self.drain_range(range).map(|(_, a)| a).collect();
+        self.compact();
+        removed
+    }
+
+    /// Returns a snapshot of the memory usage.
+    pub fn capacity_report(&self) -> CapacityReport {
+        let live = self.len();
+        CapacityReport {
+            live,
+            holes: self.inner.len() - live,
+            inner_len: self.inner.len(),
+            inner_capacity: self.inner.capacity(),
+            free_capacity: self.free.capacity(),
+            fragmentation_ratio: self.fragmentation_ratio(),
+        }
+    }
+
+    /// Constructs an [`OptVec<T>`] from an iterator of results,
+    /// returning the first error if there is one.
+    pub fn try_from_iter<E, I: IntoIterator<Item = Result<T, E>>>(iter: I) -> Result<Self, E> {
+        let inner = iter
+            .into_iter()
+            .map(|a| a.map(Some))
+            .collect::<Result<_, E>>()?;
+        Ok(Self::from_parts(inner, Vec::new()))
+    }
+
+    /// Sorts the free list so that the following pushes fill the lowest free indices first.
+    /// Useful after [`OptVec::from_parts()`] to make the reuse order predictable.
+    pub fn normalize_free_list(&mut self) {
+        self.free.sort_unstable_by(|a, b| b.cmp(a));
+    }
+
+    /// Returns a clone of `self` whose inner vector can hold at least `capacity` slots.
+    /// Auto-compaction is disabled on the clone, as with [`Clone`].
+    pub fn clone_with_capacity(&self, capacity: usize) -> OptVec<T>
+    where
+        T: Clone,
+    {
+        let mut inner = Vec::with_capacity(capacity.max(self.inner.len()));
+        inner.extend(self.inner.iter().cloned());
+        OptVec {
+            inner,
+            free: self.free.clone(),
+            reserved: self.reserved.clone(),
+            policy: self.policy,
+            auto_compact: None,
+            on_compact: None,
+            churn: ChurnStats::default(),
+            max_len: self.max_len,
+        }
+    }
+
+    /// Returns `true` if `index` is in bounds and in the free list.
+    /// Slots reserved by [`OptVec::allocate_slot()`] are not free.
+    ///
+    /// This takes `O(log n)` time with [`ReusePolicy::LowestIndex`], since the free list is sorted,
+    /// and `O(n)` time in the length of the free list otherwise.
+    pub fn is_free(&self, index: usize) -> bool {
+        matches!(self.inner.get(index), Some(None)) && self.free_position(index).is_some()
+    }
+
+    /// Same as [`OptVec::compact()`], but keeps the length of the inner vector
+    /// by leaving the slots after the elements as free space.
+    pub fn compact_keep_capacity(&mut self) -> Vec<(usize, usize)> {
+        let len = self.inner.len();
+        let remap = self.compact();
+        self.grow_to(len);
+        remap
+    }
+
+    /// Returns an iterator over the elements at each of the `indices`, in the same order,
+    /// yielding [`None`] for indices that are out of bounds or free space.
+    pub fn values_at<'a>(
+        &'a self,
+        indices: &'a [usize],
+    ) -> impl Iterator<Item = Option<&'a T>> + 'a {
+        indices.iter().map(move |&i| self.get(i))
+    }
+
+    /// Inserts each value at its index as with [`OptVec::insert_at()`],
+    /// and returns the previous elements in the same order.
+    pub fn set_many(&mut self, updates: Vec<(usize, T)>) -> Vec<Option<T>> {
+        updates
+            .into_iter()
+            .map(|(index, value)| self.insert_at(index, value))
+            .collect()
+    }
+
+    /// Replaces every element for which `f` returns `false` with its default value.
+    /// Unlike [`OptVec::retain()`], no slot becomes free space.
+    pub fn retain_or_reset<F: FnMut(&T) -> bool>(&mut self, mut f: F)
+    where
+        T: Default,
+    {
+        for a in self.inner.iter_mut().flatten() {
+            if !f(a) {
+                *a = T::default();
+            }
+        }
+    }
+
+    /// Returns the indices that have an element in `self` but not in `other`, in ascending order.
+    pub fn index_difference<U>(&self, other: &OptVec<U>) -> Vec<usize> {
+        self.iter_from(0)
+            .filter(|&(i, _)| other.get(i).is_none())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the indices that have an element in both `self` and `other`, in ascending order.
+    pub fn index_intersection<U>(&self, other: &OptVec<U>) -> Vec<usize> {
+        self.inner
+            .iter()
+            .zip(&other.inner)
+            .enumerate()
+            .filter(|(_, (a, b))| a.is_some() && b.is_some())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Moves each element from `old_index` to `new_index` for every `(old_index, new_index)` in `remap`.
+    /// Targets out of bounds pad the inner vector with free space, and sources that are
+    /// not targets themselves become free space.
+    ///
+    /// Nothing is moved if `remap` is invalid: every source must have an element,
+    /// and every target must be free space or a source itself. Neither may appear twice.
+    pub fn apply_remap(&mut self, remap: &[(usize, usize)]) -> Result<(), RemapError> {
+        let mut sources = BTreeSet::new();
+        for &(old, _) in remap {
+            if self.get(old).is_none() {
+                return Err(RemapError::SourceNotLive { index: old });
+            }
+            if !sources.insert(old) {
+                return Err(RemapError::DuplicateSource { index: old });
+            }
+        }
+        let mut targets = BTreeSet::new();
+        for &(_, new) in remap {
+            if !targets.insert(new) {
+                return Err(RemapError::DuplicateTarget { index: new });
+            }
+            let occupied = match self.inner.get(new) {
+                Some(Some(_)) => !sources.contains(&new),
+                Some(None) => self.reserved.contains(&new),
+                None => false,
+            };
+            if occupied {
+                return Err(RemapError::TargetOccupied { index: new });
+            }
+        }
+
+        let values: Vec<T> = remap
+            .iter()
+            .filter_map(|&(old, _)| self.inner[old].take())
+            .collect();
+        for (&(_, new), value) in remap.iter().zip(values) {
+            self.place(new, value);
+        }
+        for old in sources.difference(&targets) {
+            self.add_free(*old);
+        }
+        Ok(())
+    }
+
+    /// Returns the indices that have an element, in ascending order.
+    pub fn live_indices_sorted(&self) -> Vec<usize> {
+        let mut indices = Vec::with_capacity(self.live_count());
+        indices.extend(self.iter_from(0).map(|(i, _)| i));
+        indices
+    }
+
+    /// Calls `f` with the element at position `index` and returns `true`,
+    /// or returns `false` without calling `f` if it is out of bounds or free space.
+    pub fn update<F: FnOnce(&mut T)>(&mut self, index: usize, f: F) -> bool {
+        match self.get_mut(index) {
+            Some(a) => {
+                f(a);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pops up to `n` elements and returns them in the order they were popped.
+    pub fn pop_n(&mut self, n: usize) -> Vec<T> {
+        let mut values = Vec::with_capacity(n.min(self.inner.len()));
+        while values.len() < n {
+            match self.pop() {
+                Some(a) => values.push(a),
+                None => break,
+            }
+        }
+        values
+    }
+
+    /// Same as [`OptVec::push()`], but also returns a mutable reference to the pushed element.
+    pub fn push_returning_mut(&mut self, value: T) -> (usize, &mut T) {
+        let index = self.push(value);
+        (index, self.inner[index].as_mut().unwrap())
+    }
+
+    /// Same as [`OptVec::retain()`], but processes at most `batch` elements
+    /// starting at index `cursor`, so that the work can be spread over several calls.
+    /// Start with a cursor of `0` and pass the returned cursor to the next call.
+    /// ## Panic
+    /// Panics if `batch` is 0.
+    pub fn retain_batched<F: FnMut(&T) -> bool>(
+        &mut self,
+        cursor: usize,
+        batch: usize,
+        mut f: F,
+    ) -> RetainProgress {
+        assert!(batch != 0, "batch size must be non-zero");
+        let mut processed = 0;
+        for i in cursor..self.inner.len() {
+            if processed == batch {
+                return RetainProgress::Pending { cursor: i };
+            }
+            if let Some(a) = &self.inner[i] {
+                processed += 1;
+                if !f(a) {
+                    self.inner[i] = None;
+                    self.free_index(i);
+                }
+            }
+        }
+        RetainProgress::Done
+    }
+
+    /// Swaps the contents of `self` and `other`, including their free lists, reserved slots
+    /// and reuse policies, without copying or reallocating.
+    /// The other settings, the compact callback and the churn counters stay with each vector.
+    pub fn swap_inner(&mut self, other: &mut OptVec<T>) {
+        core::mem::swap(&mut self.inner, &mut other.inner);
+        core::mem::swap(&mut self.free, &mut other.free);
+        core::mem::swap(&mut self.reserved, &mut other.reserved);
+        core::mem::swap(&mut self.policy, &mut other.policy);
+    }
+
+    /// Converts the [`OptVec<T>`] into a map from index to element.
+    #[cfg(feature = "std")]
+    pub fn into_map(self) -> std::collections::HashMap<usize, T> {
+        self.inner
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, a)| a.map(|a| (i, a)))
+            .collect()
+    }
+
+    /// Constructs an [`OptVec<T>`] placing each value at its key.
+    /// The indices without a key become free space.
+    #[cfg(feature = "std")]
+    pub fn from_map(map: std::collections::HashMap<usize, T>) -> Self {
+        let len = map.keys().max().map_or(0, |&i| i + 1);
+        let mut inner = Vec::with_capacity(len);
+        inner.resize_with(len, || None);
+        for (i, a) in map {
+            inner[i] = Some(a);
+        }
+        let mut v = Self::from_parts(inner, Vec::new());
+        v.rebuild_free_list();
+        v
+    }
+
+    /// Removes every element equal to `value` and returns how many were removed.
+    /// The remaining elements keep their index.
+    pub fn remove_all_eq(&mut self, value: &T) -> usize
+    where
+        T: PartialEq,
+    {
+        self.retain_count(|a| a != value)
+    }
+
+    /// Returns a clone of the element at position `index`,
+    /// or the default value if it is out of bounds or free space.
+    pub fn get_or_default(&self, index: usize) -> T
+    where
+        T: Clone + Default,
+    {
+        self.get(index).cloned().unwrap_or_default()
+    }
+
+    /// Pushes each value, filling free space first, and returns their indices.
+    /// Capacity for the values that do not fit in the free space is reserved upfront.
+    pub fn insert_many(&mut self, values: impl IntoIterator<Item = T>) -> Vec<usize> {
+        let values = values.into_iter();
+        let (lower, _) = values.size_hint();
+        self.inner.reserve(lower.saturating_sub(self.free.len()));
+        let mut indices = Vec::with_capacity(lower);
+        indices.extend(values.map(|a| self.push(a)));
+        indices
+    }
+
+    /// Makes [`OptVec::push()`], [`OptVec::remove()`], [`OptVec::remove_if_present()`],
+    /// [`OptVec::pop()`] and [`OptVec::pop_front()`] run [`OptVec::compact()`] automatically
+    /// whenever the ratio of free space exceeds `ratio`, or disables it with [`None`].
+    /// Other methods that remove elements never compact.
+    /// Auto-compaction is skipped while there are slots reserved by [`OptVec::allocate_slot()`].
+    ///
+    /// Note that this changes the index of the elements; set a callback with
+    /// [`OptVec::set_compact_callback()`] to be told how they moved.
+    ///
+    /// ## Panic
+    /// Panics if `ratio` is not within `0.0..=1.0`.
+    pub fn set_auto_compact(&mut self, ratio: Option<f64>) {
+        if let Some(ratio) = ratio {
+            assert!(
+                (0.0..=1.0).contains(&ratio),
+                "compact ratio must be within 0.0..=1.0"
+            );
+        }
+        self.auto_compact = ratio;
+    }
+
+    /// Sets a callback that receives the `(old_index, new_index)` of each moved element
+    /// whenever auto-compaction runs. Neither the callback nor auto-compaction
+    /// is cloned along with the vector.
+    pub fn set_compact_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&[(usize, usize)]) + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    {
+        self.on_compact = Some(Box::new(callback));
+    }
+
+    /// Returns an iterator over the elements and their index, from the highest index to the lowest.
+    pub fn iter_rev(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.inner
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(i, a)| a.as_ref().map(|a| (i, a)))
+    }
+
+    /// Inserts `by` slots of free space at the front, moving every element `by` positions up,
+    /// and rebuilds the free list so that the lowest free index is reused first.
+    ///
+    /// This takes O(n) time, as every slot is moved.
+    pub fn shift_indices(&mut self, by: usize) {
+        if by == 0 {
+            return;
+        }
+        self.inner.splice(0..0, (0..by).map(|_| None));
+        for i in &mut self.reserved {
+            *i += by;
+        }
+        self.rebuild_free_list();
+    }
+
+    /// Removes the elements for which `f` returns `true` and returns them in a new vector,
+    /// where each of them keeps its index. The new vector has the same reuse policy as `self`.
+    pub fn split_off_where<F: FnMut(&T) -> bool>(&mut self, mut f: F) -> OptVec<T> {
+        let mut inner = Vec::new();
+        for i in 0..self.inner.len() {
+            if self.inner[i].as_ref().map_or(false, &mut f) {
+                inner.resize_with(i, || None);
+                inner.push(self.take(i));
+            }
+        }
+        self.derived(inner)
+    }
+
+    /// Returns the number of pushes and removals since the last call, and resets them to zero.
+    pub fn take_churn_stats(&mut self) -> ChurnStats {
+        core::mem::take(&mut self.churn)
+    }
+
+    /// Returns the elements whose index lies within `range`, in index order.
+    /// The range is clamped to [`OptVec::inner_len()`].
+    pub fn slice_live(&self, range: Range<usize>) -> Vec<&T> {
+        let end = range.end.min(self.inner.len());
+        let start = range.start.min(end);
+        self.inner[start..end].iter().flatten().collect()
+    }
+
+    /// Returns the limit on the number of elements set by [`OptVec::set_max_len()`]
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    /// Limits the number of elements [`OptVec::push_bounded()`] accepts, or removes the limit with [`None`].
+    /// Elements already in the vector are kept even if they exceed the limit.
+    pub fn set_max_len(&mut self, max_len: Option<usize>) {
+        self.max_len = max_len;
+    }
+
+    /// Same as [`OptVec::push()`], but gives `value` back
+    /// if the number of elements has reached the limit set by [`OptVec::set_max_len()`].
+    pub fn push_bounded(&mut self, value: T) -> Result<usize, T> {
+        match self.max_len {
+            Some(max_len) if self.live_count() >= max_len => Err(value),
+            _ => Ok(self.push(value)),
+        }
+    }
+
+    /// Retains only the elements for which `f` returns `true`, and returns
+    /// the index of the kept elements and of the removed ones, both in index order.
+    /// The kept elements keep their index.
+    pub fn retain_reporting<F: FnMut(usize, &T) -> bool>(
+        &mut self,
+        mut f: F,
+    ) -> (Vec<usize>, Vec<usize>) {
+        let mut kept = Vec::new();
+        let mut removed = Vec::new();
+        for i in 0..self.inner.len() {
+            match &self.inner[i] {
+                Some(a) if f(i, a) => kept.push(i),
+                Some(_) => {
+                    self.inner[i] = None;
+                    self.free_index(i);
+                    removed.push(i);
+                }
+                None => {}
+            }
+        }
+        (kept, removed)
+    }
+
+    /// Moves every element into `dest` with [`OptVec::push()`] in index order,
+    /// so that the free space of `dest` is filled first, and leaves `self` empty.
+    /// The capacity of `self` is kept.
+    pub fn drain_compact_into(&mut self, dest: &mut OptVec<T>) {
+        for a in self.inner.drain(..).flatten() {
+            dest.push(a);
+            self.churn.removed += 1;
+        }
+        self.free.clear();
+        self.reserved.clear();
+    }
+
+    /// Moves up to `n` elements with the highest index into the free space with the lowest index,
+    /// then, if any element was moved, removes the free space at the end of the inner vector.
+    /// Returns `(old_index, new_index)` for each element that was moved.
+    ///
+    /// Unlike [`OptVec::compact()`], the work per call is bounded by `n`,
+    /// so the vector can be defragmented a little at a time.
+    pub fn compact_front(&mut self, n: usize) -> Vec<(usize, usize)> {
+        let mut remap = Vec::new();
+        let mut lo = 0;
+        let mut hi = self.inner.len();
+        while remap.len() < n {
+            while lo < hi && (self.inner[lo].is_some() || self.reserved.contains(&lo)) {
+                lo += 1;
+            }
+            while hi > lo && self.inner[hi - 1].is_none() {
+                hi -= 1;
+            }
+            if hi <= lo + 1 {
+                break;
+            }
+            hi -= 1;
+            self.inner.swap(lo, hi);
+            self.unfree(lo);
+            self.add_free(hi);
+            remap.push((hi, lo));
+        }
+        if !remap.is_empty() {
+            self.reclaim();
+        }
+        remap
+    }
+
+    /// Returns a clone of the element at position `index`,
+    /// or [`None`] if it is out of bounds or free space.
+    pub fn get_cloned(&self, index: usize) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.get(index).cloned()
+    }
+
+    /// Reserves capacity so that the inner vector and the free list
+    /// can hold at least as many entries as those of `other` without reallocating.
+    pub fn reserve_like(&mut self, other: &OptVec<T>) {
+        let inner = other.inner.capacity().saturating_sub(self.inner.len());
+        self.inner.reserve(inner);
+        let free = other.free.capacity().saturating_sub(self.free.len());
+        self.free.reserve(free);
+    }
+
+    /// Checks that the free list and the reserved slots match the free space in the inner vector,
+    /// and returns every violation found.
+    /// Useful after building a vector with [`OptVec::from_parts()`].
+    pub fn validate(&self) -> Result<(), Vec<Inconsistency>> {
+        let len = self.inner.len();
+        let mut errors = Vec::new();
+        let mut listed = alloc::vec![false; len];
+        for &index in &self.free {
+            if index >= len {
+                errors.push(Inconsistency::FreeOutOfBounds { index });
+            } else if self.inner[index].is_some() {
+                errors.push(Inconsistency::FreeOccupied { index });
+            } else if listed[index] {
+                errors.push(Inconsistency::DuplicateFree { index });
+            } else {
+                listed[index] = true;
+            }
+        }
+        for &index in &self.reserved {
+            if index >= len || self.inner[index].is_some() || listed[index] {
+                errors.push(Inconsistency::ReservedInvalid { index });
+            } else {
+                listed[index] = true;
+            }
+        }
+        if self.policy == ReusePolicy::LowestIndex && self.free.windows(2).any(|w| w[0] <= w[1]) {
+            errors.push(Inconsistency::FreeUnsorted);
+        }
+        errors.extend(
+            (0..len)
+                .filter(|&i| self.inner[i].is_none() && !listed[i])
+                .map(|index| Inconsistency::MissingFree { index }),
+        );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Brings the vector back to a state that passes [`OptVec::validate()`],
+    /// by dropping invalid reserved slots and rebuilding the free list from the free space
+    /// with [`OptVec::rebuild_free_list()`]. Elements are never touched.
+    pub fn repair(&mut self) {
+        self.reserved.sort_unstable();
+        self.reserved.dedup();
+        self.rebuild_free_list();
+    }
+
+    /// Rebuilds the free list from the free space in the inner vector,
+    /// so that the lowest free index is reused first.
+    /// Slots reserved by [`OptVec::allocate_slot()`] are left out while they are still empty.
+    pub fn rebuild_free_list(&mut self) {
+        let inner = &self.inner;
+        self.reserved
+            .retain(|&i| inner.get(i).map_or(false, Option::is_none));
+        let reserved = &self.reserved;
+        self.free.clear();
+        self.free.extend(
+            (0..inner.len())
+                .rev()
+                .filter(|&i| inner[i].is_none() && !reserved.contains(&i)),
+        );
+    }
+
+    /// Takes the elements out, lets `rotate` reorder them and puts them back into the same slots.
+    fn rotate_with<F: FnOnce(&mut [T])>(&mut self, rotate: F) {
+        let slots: Vec<usize> = (0..self.inner.len())
+            .filter(|&i| self.inner[i].is_some())
+            .collect();
+        if slots.is_empty() {
+            return;
+        }
+        let mut values: Vec<T> = slots.iter().filter_map(|&i| self.inner[i].take()).collect();
+        rotate(&mut values);
+        for (i, a) in slots.into_iter().zip(values) {
+            self.inner[i] = Some(a);
+        }
+    }
+
+    /// Constructs an [`OptVec<U>`] from `inner` with the same reuse policy as `self`.
+    fn derived<U>(&self, inner: Vec<Option<U>>) -> OptVec<U> {
+        let mut v = OptVec {
+            inner,
+            policy: self.policy,
+            ..OptVec::new()
+        };
+        v.rebuild_free_list();
+        v
+    }
+
+    /// Returns the number of elements in `O(1)` time from the length of the free list.
+    /// Unlike [`OptVec::len()`], the result is only exact if the free list is consistent.
+    fn live_count(&self) -> usize {
+        self.inner
+            .len()
+            .saturating_sub(self.free.len() + self.reserved.len())
+    }
+
+    /// Same as [`OptVec::remove()`], but never triggers auto-compaction,
+    /// so that the index of other elements stays stable.
+    fn take(&mut self, index: usize) -> Option<T> {
+        if self.inner[index].is_some() {
+            self.free_index(index);
+            self.inner[index].take()
+        } else {
+            None
+        }
+    }
+
+    /// Runs [`OptVec::compact()`] if the ratio set by [`OptVec::set_auto_compact()`] is exceeded.
+    fn auto_compact_check(&mut self) {
+        let ratio = match self.auto_compact {
+            Some(ratio) => ratio,
+            None => return,
+        };
+        if self.free.is_empty()
+            || !self.reserved.is_empty()
+            || self.free.len() as f64 / self.inner.len() as f64 <= ratio
+        {
+            return;
+        }
+        let remap = self.compact();
+        if let Some(on_compact) = self.on_compact.as_mut() {
+            on_compact(&remap);
+        }
+    }
+
+    /// Adds the slot of an element that was just removed to the free list,
+    /// and counts the removal for [`OptVec::take_churn_stats()`].
+    fn free_index(&mut self, index: usize) {
+        self.churn.removed += 1;
+        self.add_free(index);
+    }
+
+    /// Adds `index` to the free list according to the reuse policy.
+    ///
+    /// With [`ReusePolicy::LowestIndex`] the free list is kept in descending order,
+    /// so that the lowest index is always at the end.
+    fn add_free(&mut self, index: usize) {
+        match self.policy {
+            ReusePolicy::Lifo => self.free.push(index),
+            ReusePolicy::LowestIndex => {
+                let p = self.free.partition_point(|&i| i > index);
+                self.free.insert(p, index);
+            }
+        }
+    }
+
+    /// Returns the position of `index` in the free list.
+    fn free_position(&self, index: usize) -> Option<usize> {
+        match self.policy {
+            ReusePolicy::Lifo => self.free.iter().position(|&i| i == index),
+            ReusePolicy::LowestIndex => self.free.binary_search_by(|i| index.cmp(i)).ok(),
+        }
+    }
+
+    /// Removes `index` from the free list.
+    fn unfree(&mut self, index: usize) {
+        if let Some(p) = self.free_position(index) {
+            self.free.remove(p);
+        }
+    }
+
+    /// Removes `index` from the reserved slots and returns `true` if it was reserved.
+    fn unreserve(&mut self, index: usize) -> bool {
+        match self.reserved.iter().position(|&i| i == index) {
+            Some(p) => {
+                self.reserved.swap_remove(p);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the number of free slots at the end of the inner vector, stopping at reserved slots.
+    fn trailing_free(&self) -> usize {
+        self.inner
+            .iter()
+            .enumerate()
+            .rev()
+            .take_while(|(i, a)| a.is_none() && !self.reserved.contains(i))
+            .count()
+    }
+}
+
+/// A guard returned by [`OptVec::edit()`] that rebuilds the free list once when dropped.
+#[derive(Debug)]
+pub struct EditGuard<'a, T> {
+    vec: &'a mut OptVec<T>,
+}
+
+impl<'a, T> EditGuard<'a, T> {
+    /// Replaces the slot at position `index` with `value` and returns the previous one.
+    /// If `index` is out of bounds, the inner vector is padded with free space.
+    pub fn set(&mut self, index: usize, value: Option<T>) -> Option<T> {
+        let vec = &mut *self.vec;
+        if index >= vec.inner.len() {
+            vec.inner.resize_with(index + 1, || None);
+        }
+        match (vec.inner[index].is_some(), value.is_some()) {
+            (false, true) => vec.churn.pushed += 1,
+            (true, false) => vec.churn.removed += 1,
+            _ => {}
+        }
+        core::mem::replace(&mut vec.inner[index], value)
+    }
+
+    /// Returns a mutable reference to the element at position `index`,
+    /// or [`None`] if it is out of bounds or free space.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.vec.get_mut(index)
+    }
+}
+
+impl<'a, T> Drop for EditGuard<'a, T> {
+    fn drop(&mut self) {
+        self.vec.rebuild_free_list();
+    }
+}
+
+impl<T> Index<usize> for OptVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.inner[index].as_ref().unwrap()
+    }
+}
+
+impl<T> IndexMut<usize> for OptVec<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.inner[index].as_mut().unwrap()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a OptVec<T> {
+    type Item = &'a Option<T>;
+
+    type IntoIter = Iter<'a, Option<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut OptVec<T> {
+    type Item = &'a mut Option<T>;
+
+    type IntoIter = IterMut<'a, Option<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.inner.iter_mut()
+    }
+}
+
+impl<T> Extend<(usize, T)> for OptVec<T> {
+    fn extend<I: IntoIterator<Item = (usize, T)>>(&mut self, iter: I) {
+        for (index, value) in iter {
+            self.insert_at(index, value);
+        }
+    }
+}
+
+impl<T> AsRef<[Option<T>]> for OptVec<T> {
+    fn as_ref(&self) -> &[Option<T>] {
+        &self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OptVec;
+
+    #[test]
+    fn ok() {
+        let mut v: OptVec<i32> = OptVec::new();
+        assert_eq!(v.push(1), 0);
+        assert_eq!(v.push(2), 1);
+        assert_eq!(v.push(3), 2);
+        assert_eq!(v.push(4), 3);
+        assert_eq!(v.push(5), 4);
+
+        assert_eq!(v.pop(), Some(5));
+        assert_eq!(v.inner, vec![Some(1), Some(2), Some(3), Some(4)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+
+        assert_eq!(v.remove(1), Some(2));
+        assert_eq!(v.inner, vec![Some(1), None, Some(3), Some(4)]);
+        assert_eq!(v.free, vec![1]);
+
+        assert_eq!(v.remove(1), None);
+        assert_eq!(v.inner, vec![Some(1), None, Some(3), Some(4)]);
+        assert_eq!(v.free, vec![1]);
+
+        assert_eq!(v.push(5), 1);
+        assert_eq!(v.inner, vec![Some(1), Some(5), Some(3), Some(4)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+    }
+
+    #[should_panic]
+    #[test]
+    fn err() {
+        let mut v = OptVec::new();
+        v.push(1);
+        v.remove(1);
+    }
+
+    #[test]
+    fn filter_to_new() {
+        let mut v = OptVec::new();
+        for i in 1..=5 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let w = v.filter_to_new(|a| a % 2 == 1);
+        assert_eq!(w.inner, vec![Some(1), None, Some(3), None, Some(5)]);
+        assert_eq!(w.free, vec![3, 1]);
+        assert_eq!(v.inner, vec![Some(1), None, Some(3), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn count_live_in_range() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(3);
+
+        assert_eq!(v.count_live_in_range(1..4), 1);
+        assert_eq!(v.count_live_in_range(0..100), 4);
+        assert_eq!(v.count_live_in_range(10..20), 0);
+    }
+
+    #[test]
+    fn fill_default() {
+        let mut v = OptVec::new();
+        for i in 1..=4 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(2);
+
+        v.fill_default(3);
+        assert_eq!(v.len(), 5);
+        assert_eq!(v.inner, vec![Some(0), Some(2), Some(0), Some(4), Some(0)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+    }
+
+    #[test]
+    fn highest_free_index() {
+        let mut v = OptVec::new();
+        assert_eq!(v.highest_free_index(), None);
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(4);
+        v.remove(1);
+        v.remove(2);
+        assert_eq!(v.highest_free_index(), Some(4));
+    }
+
+    #[test]
+    fn as_ref() {
+        fn holes(a: &[Option<i32>]) -> usize {
+            a.iter().filter(|a| a.is_none()).count()
+        }
+
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+        v.push(3);
+        v.remove(1);
+        assert_eq!(v.as_ref(), &[Some(1), None, Some(3)]);
+        assert_eq!(holes(v.as_ref()), 1);
+    }
+
+    #[test]
+    fn compact() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(3);
+        let mut w = v.clone();
+
+        let mut moves = std::collections::BTreeMap::new();
+        v.compact_with(|old, new| {
+            moves.insert(old, new);
+        });
+        assert_eq!(v.inner, vec![Some(1), Some(2), Some(4), Some(5)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+
+        let remap = w.compact();
+        assert_eq!(remap, vec![(1, 0), (2, 1), (4, 2), (5, 3)]);
+        assert_eq!(moves.into_iter().collect::<Vec<_>>(), remap);
+        assert_eq!(v, w);
+    }
+
+    #[test]
+    fn split_at_ordinal() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(2);
+        v.remove(4);
+
+        let w = v.split_at_ordinal(2);
+        assert_eq!(v.inner, vec![None, Some(1), None, Some(3)]);
+        assert_eq!(v.free, vec![0, 2]);
+        assert_eq!(w.inner, vec![Some(5)]);
+        assert_eq!(w.free, vec![] as Vec<usize>);
+
+        assert_eq!(v.split_at_ordinal(5), OptVec::new());
+        assert_eq!(v.len(), 2);
+    }
+
+    #[test]
+    fn reclaim() {
+        let mut v = OptVec::with_capacity(8);
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(5);
+        v.remove(4);
+        let capacity = v.inner.capacity();
+
+        assert_eq!(v.reclaim(), 2);
+        assert_eq!(v.inner_len(), 4);
+        assert_eq!(v.inner.capacity(), capacity);
+        assert_eq!(v.free, vec![1]);
+        assert_eq!(v.reclaim(), 0);
+    }
+
+    #[test]
+    fn get_pair_or_single_mut() {
+        use super::PairMut;
+
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+        v.push(3);
+        v.remove(1);
+
+        assert_eq!(v.get_pair_or_single_mut(0, 0), PairMut::Single(&mut 1));
+        match v.get_pair_or_single_mut(2, 0) {
+            PairMut::Both(a, b) => {
+                core::mem::swap(a, b);
+            }
+            _ => panic!(),
+        }
+        assert_eq!(v.inner, vec![Some(3), None, Some(1)]);
+
+        assert_eq!(v.get_pair_or_single_mut(0, 1), PairMut::Missing);
+        assert_eq!(v.get_pair_or_single_mut(1, 1), PairMut::Missing);
+        assert_eq!(v.get_pair_or_single_mut(0, 3), PairMut::Missing);
+    }
+
+    #[test]
+    fn insert_at() {
+        let mut v = OptVec::new();
+        v.push("x");
+        assert_eq!(v.insert_at(3, "a"), None);
+        assert_eq!(v.inner, vec![Some("x"), None, None, Some("a")]);
+        assert_eq!(v.free, vec![2, 1]);
+
+        assert_eq!(v.insert_at(1, "b"), None);
+        assert_eq!(v.free, vec![2]);
+        assert_eq!(v.insert_at(0, "c"), Some("x"));
+        assert_eq!(v.inner, vec![Some("c"), Some("b"), None, Some("a")]);
+    }
+
+    #[test]
+    fn extend_at() {
+        let mut v = OptVec::new();
+        v.push("x");
+        v.extend(vec![(5, "a"), (2, "b"), (5, "c")]);
+        assert_eq!(
+            v.inner,
+            vec![Some("x"), None, Some("b"), None, None, Some("c")]
+        );
+        assert_eq!(v.free, vec![4, 3, 1]);
+        assert_eq!(v.push("d"), 1);
+    }
+
+    #[test]
+    fn dedup_by_key() {
+        let mut v = OptVec::new();
+        for a in [(1, 'a'), (1, 'b'), (2, 'c'), (2, 'd'), (1, 'e')] {
+            v.push(a);
+        }
+        v.remove(2);
+
+        v.dedup_by_key(|a| a.0);
+        assert_eq!(
+            v.inner,
+            vec![Some((1, 'a')), None, None, Some((2, 'd')), Some((1, 'e'))]
+        );
+        assert_eq!(v.free, vec![2, 1]);
+    }
+
+    #[test]
+    fn live_slice_view() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        assert_eq!(&*v.live_slice_view(), &[0, 2, 3]);
+        assert_eq!(v.live_slice_view().into_owned(), v.clone().to_vec());
+    }
+
+    #[test]
+    fn remove_if_present() {
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+
+        assert!(v.remove_if_present(0));
+        assert!(!v.remove_if_present(0));
+        assert!(!v.remove_if_present(5));
+        assert_eq!(v.inner, vec![None, Some(2)]);
+        assert_eq!(v.free, vec![0]);
+    }
+
+    #[test]
+    fn stable_partition() {
+        let mut v = OptVec::new();
+        for i in 0..8 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(5);
+
+        assert_eq!(v.stable_partition(|a| a % 2 == 0), 3);
+        assert_eq!(
+            v.inner,
+            vec![Some(2), Some(4), Some(6), Some(1), Some(3), Some(7)]
+        );
+        assert_eq!(v.free, vec![] as Vec<usize>);
+    }
+
+    #[test]
+    fn reuse_policy() {
+        use super::ReusePolicy;
+
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        assert_eq!(v.reuse_policy(), ReusePolicy::Lifo);
+        v.remove(1);
+        v.remove(4);
+        assert_eq!(v.push(6), 4);
+
+        v.remove(3);
+        v.set_reuse_policy(ReusePolicy::LowestIndex);
+        assert_eq!(v.reuse_policy(), ReusePolicy::LowestIndex);
+        assert_eq!(v.free, vec![3, 1]);
+        v.remove(2);
+        assert_eq!(v.free, vec![3, 2, 1]);
+        assert_eq!(v.push(7), 1);
+        assert_eq!(v.push(8), 2);
+        assert_eq!(v.insert_at(7, 9), None);
+        assert_eq!(v.free, vec![6, 3]);
+        assert_eq!(v.push(10), 3);
+
+        v.set_reuse_policy(ReusePolicy::Lifo);
+        v.remove(0);
+        assert_eq!(v.push(11), 0);
+    }
+
+    #[test]
+    fn merge_fill() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(2);
+
+        let mut w = OptVec::new();
+        for i in 10..14 {
+            w.push(i);
+        }
+        w.remove(0);
+
+        assert_eq!(v.merge_fill(w), vec![(1, 2), (2, 1), (3, 4)]);
+        assert_eq!(
+            v.inner,
+            vec![Some(0), Some(12), Some(11), Some(3), Some(13)]
+        );
+        assert_eq!(v.free, vec![] as Vec<usize>);
+    }
+
+    #[test]
+    fn value_eq() {
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+        v.push(3);
+        v.remove(1);
+
+        let mut w = OptVec::new();
+        w.push(0);
+        w.push(1);
+        w.push(3);
+        w.remove(0);
+
+        assert_ne!(v, w);
+        assert!(v.value_eq(&w));
+        w.push(4);
+        assert!(!v.value_eq(&w));
+    }
+
+    #[test]
+    fn hash_values() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hasher;
+
+        fn hash(v: &OptVec<i32>) -> u64 {
+            let mut state = DefaultHasher::new();
+            v.hash_values(&mut state);
+            state.finish()
+        }
+
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+        v.push(3);
+        v.remove(1);
+
+        let mut w = OptVec::new();
+        w.push(0);
+        w.push(1);
+        w.push(3);
+        w.remove(0);
+
+        assert_eq!(hash(&v), hash(&w));
+        w.push(4);
+        assert_ne!(hash(&v), hash(&w));
+    }
+
+    #[test]
+    fn try_get() {
+        use super::AccessError;
+
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+        v.remove(1);
+
+        assert_eq!(v.get(0), Some(&1));
+        assert_eq!(v.get(1), None);
+        assert_eq!(v.try_get(0), Ok(&1));
+        assert_eq!(v.try_get(1), Err(AccessError::Freed { index: 1 }));
+        assert_eq!(
+            v.try_get(2),
+            Err(AccessError::OutOfBounds {
+                index: 2,
+                inner_len: 2
+            })
+        );
+    }
+
+    #[test]
+    fn retain_map() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        v.retain_map(|a| if a % 2 == 0 { Some(a * 10) } else { None });
+        assert_eq!(v.inner, vec![Some(0), None, Some(20), None, Some(40)]);
+        assert_eq!(v.free, vec![1, 3]);
+    }
+
+    #[test]
+    fn capacity_remaining() {
+        let mut v = OptVec::with_capacity(10);
+        for i in 0..4 {
+            v.push(i);
+        }
+        let growth = v.inner.capacity() - 4;
+        assert_eq!(v.capacity_remaining(), growth);
+
+        v.remove(0);
+        v.remove(2);
+        assert_eq!(v.capacity_remaining(), growth + 2);
+
+        v.push(4);
+        assert_eq!(v.capacity_remaining(), growth + 1);
+    }
+
+    #[test]
+    fn compact_if() {
+        let mut v = OptVec::new();
+        assert_eq!(v.fragmentation_ratio(), 0.0);
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(0);
+        assert_eq!(v.fragmentation_ratio(), 0.25);
+
+        assert_eq!(v.compact_if(|v| v.fragmentation_ratio() > 0.5), None);
+        assert_eq!(v.inner_len(), 4);
+
+        v.remove(2);
+        assert_eq!(
+            v.compact_if(|v| v.fragmentation_ratio() >= 0.5),
+            Some(vec![(1, 0), (3, 1)])
+        );
+        assert_eq!(v.inner, vec![Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn for_each_pair_mut() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let mut pairs = Vec::new();
+        v.for_each_pair_mut(|(i, a), (j, b)| {
+            pairs.push((i, j));
+            *a += 1;
+            *b += 1;
+        });
+        pairs.sort_unstable();
+        assert_eq!(pairs, vec![(0, 2), (0, 3), (2, 3)]);
+        assert_eq!(v.inner, vec![Some(2), None, Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn shrink_holes_only() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(4);
+        v.remove(5);
+        v.remove(2);
+
+        v.shrink_holes_only();
+        assert_eq!(v.inner, vec![Some(0), None, None, Some(3)]);
+        assert_eq!(v.free, vec![2, 1]);
+        assert_eq!(v.inner.capacity(), 4);
+    }
+
+    #[test]
+    fn reset() {
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+        v.remove(1);
+
+        v.reset(0);
+        v.reset(1);
+        v.reset(2);
+        assert_eq!(v.get(0), Some(&0));
+        assert_eq!(v.len(), 1);
+        assert_eq!(v.inner, vec![Some(0), None]);
+        assert_eq!(v.free, vec![1]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_sparse() {
+        use super::SparseFormat;
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(2);
+
+        let json = serde_json::to_string(&SparseFormat(&v)).unwrap();
+        assert_eq!(json, "[0,null,null,3]");
+        assert_eq!(serde_json::to_string(&v).unwrap(), json);
+
+        let mut w: OptVec<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(w.inner, v.inner);
+        assert_eq!(w.free, vec![2, 1]);
+        assert_eq!(w.push(4), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_compact() {
+        use super::CompactFormat;
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let json = serde_json::to_string(&CompactFormat(&v)).unwrap();
+        assert_eq!(json, "[0,2,3]");
+        let w: Vec<i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(w, v.to_vec());
+    }
+
+    #[test]
+    fn drain_range() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(2);
+
+        let mut w = v.clone();
+        assert_eq!(
+            v.drain_range(1..4).collect::<Vec<_>>(),
+            vec![(1, 1), (3, 3)]
+        );
+        assert_eq!(v.inner, vec![Some(0), None, None, None, Some(4)]);
+        assert_eq!(v.free, vec![2, 1, 3]);
+
+        assert_eq!(w.drain_range(1..4).next(), Some((1, 1)));
+        assert_eq!(w.inner, vec![Some(0), None, None, Some(3), Some(4)]);
+        assert_eq!(w.free, vec![2, 1]);
+    }
+
+    #[test]
+    fn into_vec_with_holes() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(3);
+        v.remove(1);
+
+        let inner = v.clone().into_vec_with_holes();
+        assert_eq!(inner, vec![Some(0), None, Some(2), None, Some(4)]);
+
+        let mut w = OptVec::from_parts(inner, Vec::new());
+        w.rebuild_free_list();
+        assert_eq!(w, v);
+    }
+
+    #[test]
+    fn keep_only() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+
+        v.keep_only(&[0, 2, 7].iter().copied().collect());
+        assert_eq!(v.inner, vec![Some(0), None, Some(2), None]);
+        assert_eq!(v.free, vec![1, 3]);
+    }
+
+    #[test]
+    fn grow_to() {
+        let mut v = OptVec::new();
+        v.push(0);
+        v.grow_to(4);
+        assert_eq!(v.inner_len(), 4);
+        assert_eq!(v.inner, vec![Some(0), None, None, None]);
+        assert_eq!(v.free, vec![3, 2, 1]);
+
+        v.grow_to(2);
+        assert_eq!(v.inner_len(), 4);
+        assert_eq!(v.push(1), 1);
+    }
+
+    #[test]
+    fn find_map() {
+        let mut v = OptVec::new();
+        for a in ["1", "x", "3", "4"] {
+            v.push(a);
+        }
+        v.remove(2);
+
+        assert_eq!(
+            v.find_map(|i, a| a.parse::<i32>().ok().filter(|&n| n > 1).map(|n| (i, n))),
+            Some((3, 4))
+        );
+        assert_eq!(v.find_map(|_, a| a.strip_prefix('y')), None);
+    }
+
+    #[test]
+    fn reserve_free() {
+        let mut v = OptVec::new();
+        v.push(0);
+        v.push(1);
+        v.remove(0);
+
+        v.reserve_free(10);
+        assert!(v.free.capacity() >= 11);
+        assert_eq!(v.free, vec![0]);
+
+        v.reserve_free_exact(20);
+        assert!(v.free.capacity() >= 21);
+        assert_eq!(v.free, vec![0]);
+    }
+
+    #[test]
+    fn take_all() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let mut w = v.clone();
+        assert_eq!(
+            v.take_all().collect::<Vec<_>>(),
+            vec![(0, 0), (2, 2), (3, 3)]
+        );
+        assert_eq!(v.inner, vec![None; 4]);
+        assert_eq!(v.free, vec![1, 0, 2, 3]);
+        assert_eq!(v.push(4), 3);
+
+        assert_eq!(w.take_all().take(2).count(), 2);
+        assert_eq!(w.inner, vec![None, None, None, Some(3)]);
+        assert_eq!(w.free, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn compact_stable() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(2);
+
+        let map = v.compact_stable();
+        assert_eq!(v.inner, vec![Some(1), Some(3), Some(4)]);
+        assert_eq!(map(0), None);
+        assert_eq!(map(1), Some(0));
+        assert_eq!(map(2), None);
+        assert_eq!(map(4), Some(2));
+        assert_eq!(map(9), None);
+    }
+
+    #[test]
+    fn pop_front() {
+        let mut v = OptVec::new();
+        for i in 0..3 {
+            v.push(i);
+        }
+        v.remove(0);
+
+        assert_eq!(v.pop_front(), Some((1, 1)));
+        assert_eq!(v.pop_front(), Some((2, 2)));
+        assert_eq!(v.pop_front(), None);
+        assert_eq!(v.free, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn zip_with() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let mut w = OptVec::new();
+        for a in ["a", "b", "c", "d", "e"] {
+            w.push(a);
+        }
+        w.remove(2);
+
+        let z = v.zip_with(&w, |a, b| format!("{}{}", b, a));
+        assert_eq!(
+            z.inner,
+            vec![Some("a0".to_string()), None, None, Some("d3".to_string())]
+        );
+        assert_eq!(z.free, vec![2, 1]);
+    }
+
+    #[test]
+    fn get_disjoint_mut() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        if let Some([a, b, c]) = v.get_disjoint_mut([4, 0, 2]) {
+            *a += 10;
+            *b += 20;
+            *c += 30;
+        } else {
+            panic!();
+        }
+        assert_eq!(v.inner, vec![Some(20), None, Some(32), Some(3), Some(14)]);
+
+        assert!(v.get_disjoint_mut([0, 2, 0]).is_none());
+        assert!(v.get_disjoint_mut([0, 1]).is_none());
+        assert!(v.get_disjoint_mut([0, 5]).is_none());
+        assert!(v.get_disjoint_mut([0, usize::MAX]).is_none());
+        assert!(v.get_disjoint_mut([usize::MAX, usize::MAX]).is_none());
+        assert!(v.get_disjoint_mut([]).is_some());
+    }
+
+    #[test]
+    fn occupancy_bitmap() {
+        let mut v = OptVec::new();
+        assert_eq!(v.occupancy_bitmap(), vec![] as Vec<u64>);
+        for i in 0..70 {
+            v.push(i);
+        }
+        for i in [1, 3, 64, 69] {
+            v.remove(i);
+        }
+
+        let bitmap = v.occupancy_bitmap();
+        assert_eq!(bitmap.len(), 2);
+        let decoded: Vec<usize> = (0..128)
+            .filter(|i| bitmap[i / 64] & (1 << (i % 64)) != 0)
+            .collect();
+        let indices: Vec<usize> = (0..v.inner_len())
+            .filter(|&i| v.inner[i].is_some())
+            .collect();
+        assert_eq!(decoded, indices);
+    }
+
+    #[test]
+    fn from_occupancy() {
+        let mut v = OptVec::new();
+        for i in 0..70 {
+            v.push(i);
+        }
+        for i in [0, 3, 64, 69] {
+            v.remove(i);
+        }
+
+        let w = OptVec::from_occupancy(&v.occupancy_bitmap(), v.clone().to_vec().into_iter());
+        assert_eq!(w.inner[..69], v.inner[..69]);
+        assert_eq!(w.inner_len(), 69);
+        assert_eq!(w.free, vec![64, 3, 0]);
+    }
+
+    #[should_panic]
+    #[test]
+    fn from_occupancy_err() {
+        OptVec::from_occupancy(&[0b111], vec![1, 2].into_iter());
+    }
+
+    #[test]
+    fn iter_from() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(2);
+        v.remove(4);
+
+        assert_eq!(v.iter_from(2).collect::<Vec<_>>(), vec![(3, &3), (5, &5)]);
+        assert_eq!(v.iter_from(6).count(), 0);
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<OptVec<i32>>();
+        assert_send_sync::<OptVec<String>>();
+    }
+
+    #[test]
+    fn unwind_safe() {
+        fn assert_unwind_safe<T: std::panic::UnwindSafe + std::panic::RefUnwindSafe>() {}
+        assert_unwind_safe::<OptVec<i32>>();
+        assert_unwind_safe::<OptVec<String>>();
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn sync_opt_vec() {
+        use super::SyncOptVec;
+        use std::sync::Arc;
+        use std::thread;
+
+        let v = Arc::new(SyncOptVec::new());
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let v = Arc::clone(&v);
+                thread::spawn(move || {
+                    for i in 0..100 {
+                        v.push(t * 100 + i);
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(v.len(), 400);
+        assert!(v.remove(0).is_some());
+        assert_eq!(v.remove(400), None);
+        assert_eq!(v.get(0), None);
+        assert_eq!(v.read().free, vec![0]);
+
+        let mut values = Arc::try_unwrap(v).unwrap().into_inner().to_vec();
+        values.sort_unstable();
+        values.dedup();
+        assert_eq!(values.len(), 399);
+    }
+
+    #[test]
+    fn retain_count() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(0);
+
+        assert_eq!(v.retain_count(|a| a % 2 == 0), 3);
+        assert_eq!(v.inner, vec![None, None, Some(2), None, Some(4), None]);
+        assert_eq!(v.free, vec![0, 1, 3, 5]);
+
+        v.retain(|&a| a > 2);
+        assert_eq!(v.inner, vec![None, None, None, None, Some(4), None]);
+    }
+
+    #[test]
+    fn edit() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+
+        {
+            let mut e = v.edit();
+            assert_eq!(e.set(1, None), Some(1));
+            assert_eq!(e.set(3, None), Some(3));
+            assert_eq!(e.set(5, Some(5)), None);
+            assert_eq!(e.set(1, Some(10)), None);
+            *e.get_mut(0).unwrap() += 20;
+            assert_eq!(e.get_mut(3), None);
+        }
+        assert_eq!(
+            v.inner,
+            vec![Some(20), Some(10), Some(2), None, None, Some(5)]
+        );
+        assert_eq!(v.free, vec![4, 3]);
+    }
+
+    #[test]
+    fn reverse() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(4);
+
+        v.reverse();
+        assert_eq!(
+            v.inner,
+            vec![Some(5), None, Some(3), Some(2), None, Some(0)]
+        );
+        assert_eq!(v.free, vec![1, 4]);
+    }
+
+    #[test]
+    fn rotate() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        v.rotate_left(1);
+        assert_eq!(v.inner, vec![Some(2), None, Some(3), Some(4), Some(0)]);
+        v.rotate_right(5);
+        assert_eq!(v.inner, vec![Some(0), None, Some(2), Some(3), Some(4)]);
+        assert_eq!(v.free, vec![1]);
+
+        let mut e: OptVec<i32> = OptVec::new();
+        e.rotate_left(3);
+        assert!(e.is_empty());
+    }
+
+    #[test]
+    fn min_max() {
+        let mut v = OptVec::new();
+        assert_eq!(v.min(), None);
+        assert_eq!(v.max(), None);
+        for a in [3, 0, 1, 5, 1, 5] {
+            v.push(a);
+        }
+        v.remove(1);
+
+        assert_eq!(v.min(), Some((2, &1)));
+        assert_eq!(v.max(), Some((3, &5)));
+    }
+
+    #[test]
+    fn fold() {
+        let mut v = OptVec::new();
+        for i in 1..=5 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(3);
+
+        assert_eq!(v.fold(0, |sum, a| sum + a), 1 + 3 + 5);
+        assert_eq!(
+            v.fold(Vec::new(), |mut acc, &a| {
+                acc.push(a);
+                acc
+            }),
+            vec![1, 3, 5]
+        );
+    }
+
+    #[test]
+    fn dedup_global() {
+        let mut v = OptVec::new();
+        for a in [1, 2, 1, 3, 2, 1] {
+            v.push(a);
+        }
+        v.remove(0);
+
+        v.dedup_global();
+        assert_eq!(v.inner, vec![None, Some(2), Some(1), Some(3), None, None]);
+        assert_eq!(v.free, vec![0, 4, 5]);
+    }
+
+    #[test]
+    fn capacity_for() {
+        assert_eq!(OptVec::<i32>::capacity_for(0, 0), 0);
+        assert!(OptVec::<i32>::capacity_for(10, 0) >= 10);
+        for churn in 0..10 {
+            assert!(
+                OptVec::<i32>::capacity_for(10, churn + 1)
+                    >= OptVec::<i32>::capacity_for(10, churn)
+            );
+        }
+        assert_eq!(OptVec::<i32>::capacity_for(usize::MAX, 1), usize::MAX);
+    }
+
+    #[test]
+    fn into_iter_with_holes() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(2);
+
+        let inner = v.inner.clone();
+        assert_eq!(v.into_iter_with_holes().collect::<Vec<_>>(), inner);
+    }
+
+    #[test]
+    fn trim_free_to() {
+        let mut v = OptVec::new();
+        for i in 0..8 {
+            v.push(i);
+        }
+        for i in [1, 3, 7, 5, 6] {
+            v.remove(i);
+        }
+
+        v.trim_free_to(4);
+        assert_eq!(v.inner_len(), 7);
+        assert_eq!(v.free, vec![1, 3, 5, 6]);
+
+        v.trim_free_to(0);
+        assert_eq!(v.inner, vec![Some(0), None, Some(2), None, Some(4)]);
+        assert_eq!(v.free, vec![1, 3]);
+    }
+
+    #[test]
+    fn allocate_slot() {
+        let mut v = OptVec::new();
+        v.push(0);
+        v.push(1);
+        v.remove(0);
+
+        assert_eq!(v.allocate_slot(), 0);
+        assert_eq!(v.allocate_slot(), 2);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+        assert_eq!(v.push(3), 3);
+        assert_eq!(v.len(), 2);
+
+        v.rebuild_free_list();
+        assert_eq!(v.free, vec![] as Vec<usize>);
+        assert_eq!(v.reclaim(), 0);
+
+        assert_eq!(v.insert_at(0, 4), None);
+        assert_eq!(v.insert_at(2, 5), None);
+        assert_eq!(v.inner, vec![Some(4), Some(1), Some(5), Some(3)]);
+        assert_eq!(v.reserved, vec![] as Vec<usize>);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+    }
+
+    #[test]
+    fn release_slot() {
+        let mut v = OptVec::new();
+        v.push(0);
+        v.push(1);
+
+        let i = v.allocate_slot();
+        assert_eq!(i, 2);
+        v.release_slot(i);
+        assert_eq!(v.reserved, vec![] as Vec<usize>);
+        assert_eq!(v.free, vec![2]);
+        assert_eq!(v.push(2), 2);
+
+        v.release_slot(0);
+        assert_eq!(v.inner, vec![None, Some(1), Some(2)]);
+        v.release_slot(0);
+        v.release_slot(5);
+        assert_eq!(v.free, vec![0]);
+    }
+
+    #[test]
+    fn retain_draining_into() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+
+        let mut sink = Vec::with_capacity(8);
+        v.retain_draining_into(|a| a % 2 == 0, &mut sink);
+        assert_eq!(sink, vec![1, 3, 5]);
+        v.retain_draining_into(|&a| a < 4, &mut sink);
+        assert_eq!(sink, vec![1, 3, 5, 4]);
+        assert_eq!(v.inner, vec![Some(0), None, Some(2), None, None, None]);
+        assert_eq!(v.free, vec![1, 3, 5, 4]);
+    }
+
+    #[test]
+    fn bulk_insert_at() {
+        let mut v = OptVec::new();
+        v.push(0);
+        v.push(1);
+
+        v.bulk_insert_at(5, vec![5, 6, 7]);
+        assert_eq!(
+            v.inner,
+            vec![
+                Some(0),
+                Some(1),
+                None,
+                None,
+                None,
+                Some(5),
+                Some(6),
+                Some(7)
+            ]
+        );
+        assert_eq!(v.free, vec![4, 3, 2]);
+
+        v.bulk_insert_at(1, vec![10, 20]);
+        assert_eq!(v.inner[..3], [Some(0), Some(10), Some(20)]);
+        assert_eq!(v.free, vec![4, 3]);
+    }
+
+    #[test]
+    fn count_by() {
+        let mut v = OptVec::new();
+        for i in 0..7 {
+            v.push(i);
+        }
+        v.remove(2);
+
+        let counts = v.count_by(|a| a % 2 == 0);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&true], 3);
+        assert_eq!(counts[&false], 3);
+    }
+
+    #[test]
+    fn swap_remove_matching() {
+        let mut v = OptVec::new();
+        for i in 0..8 {
+            v.push(i);
+        }
+        v.remove(2);
+        v.remove(6);
+
+        let mut removed = v.swap_remove_matching(|a| a % 3 == 0);
+        removed.sort_unstable();
+        assert_eq!(removed, vec![0, 3]);
+        assert_eq!(v.inner, vec![Some(7), Some(1), None, Some(5), Some(4)]);
+        assert_eq!(v.free, vec![2]);
+        assert_eq!(v.len(), 4);
+
+        assert_eq!(v.swap_remove_matching(|_| true).len(), 4);
+        assert_eq!(v.inner, vec![] as Vec<Option<i32>>);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+    }
+
+    #[test]
+    fn is_dense() {
+        let mut v = OptVec::new();
+        assert!(v.is_dense());
+        for i in 0..4 {
+            v.push(i);
+        }
+        assert!(v.is_dense());
+
+        v.remove(1);
+        assert!(!v.is_dense());
+        v.compact();
+        assert!(v.is_dense());
+        assert_eq!(v.len(), v.inner_len());
+
+        v.allocate_slot();
+        assert!(!v.is_dense());
+    }
+
+    #[test]
+    fn as_dense_slice() {
+        let mut v = OptVec::new();
+        assert_eq!(v.as_dense_slice(), Some(vec![]));
+        for i in 0..3 {
+            v.push(i);
+        }
+        assert_eq!(v.as_dense_slice(), Some(vec![&0, &1, &2]));
+
+        v.remove(1);
+        assert_eq!(v.as_dense_slice(), None);
+        v.push(3);
+        assert_eq!(v.as_dense_slice().as_deref(), Some(&[&0, &3, &2][..]));
+    }
+
+    #[test]
+    fn append_slice() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(2);
+
+        assert_eq!(v.append_slice(&[10, 20, 30]), vec![2, 1, 4]);
+        assert_eq!(
+            v.inner,
+            vec![Some(0), Some(20), Some(10), Some(3), Some(30)]
+        );
+    }
+
+    #[test]
+    fn compact_sorted_by() {
+        let mut v = OptVec::new();
+        for a in [5, 0, 3, 1, 4] {
+            v.push(a);
+        }
+        v.remove(1);
+
+        assert_eq!(
+            v.compact_sorted_by(|a, b| a.cmp(b)),
+            vec![(3, 0), (2, 1), (4, 2), (0, 3)]
+        );
+        assert_eq!(v.inner, vec![Some(1), Some(3), Some(4), Some(5)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+    }
+
+    #[test]
+    fn clone_into() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let mut target = OptVec::with_capacity(8);
+        target.push(9);
+        let ptr = target.inner.as_ptr();
+        v.clone_into(&mut target);
+        assert_eq!(target, v);
+        assert_eq!(target.inner.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn windows_live() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(4);
+
+        assert_eq!(
+            v.windows_live(2).collect::<Vec<_>>(),
+            vec![vec![&0, &2], vec![&2, &3], vec![&3, &5]]
+        );
+        assert_eq!(v.windows_live(5).count(), 0);
+    }
+
+    #[test]
+    fn reserve_for_insert_at() {
+        let mut v = OptVec::new();
+        v.push(0);
+        v.reserve_for_insert_at(9);
+        assert!(v.inner.capacity() >= 10);
+        assert!(v.free.capacity() >= 8);
+
+        let ptr = (v.inner.as_ptr(), v.free.as_ptr());
+        v.insert_at(9, 9);
+        assert_eq!((v.inner.as_ptr(), v.free.as_ptr()), ptr);
+        assert_eq!(v.inner_len(), 10);
+
+        let capacity = v.inner.capacity();
+        v.reserve_for_insert_at(3);
+        assert_eq!(v.inner.capacity(), capacity);
+    }
+
+    #[test]
+    fn remove_range_compact() {
+        let mut v = OptVec::new();
+        for i in 0..7 {
+            v.push(i);
+        }
+        v.remove(3);
+        v.remove(6);
+
+        assert_eq!(v.remove_range_compact(2..5), vec![2, 4]);
+        assert_eq!(v.inner, vec![Some(0), Some(1), Some(5)]);
+        assert!(v.is_dense());
+    }
+
+    #[test]
+    fn capacity_report() {
+        let mut v = OptVec::with_capacity(8);
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(3);
+        v.push(5);
+
+        let report = v.capacity_report();
+        assert_eq!(report.live, v.len());
+        assert_eq!(report.holes, 1);
+        assert_eq!(report.inner_len, v.inner_len());
+        assert_eq!(report.inner_capacity, v.inner.capacity());
+        assert_eq!(report.free_capacity, v.free.capacity());
+        assert_eq!(report.fragmentation_ratio, v.fragmentation_ratio());
+    }
+
+    #[test]
+    fn try_from_iter() {
+        let v = OptVec::try_from_iter("1 2 3".split(' ').map(str::parse::<i32>)).unwrap();
+        assert_eq!(v.inner, vec![Some(1), Some(2), Some(3)]);
+        assert!(v.is_dense());
+
+        let mut parsed = Vec::new();
+        let e = OptVec::try_from_iter("1 2 x 4".split(' ').map(|a| {
+            parsed.push(a);
+            a.parse::<i32>()
+        }));
+        assert!(e.is_err());
+        assert_eq!(parsed, vec!["1", "2", "x"]);
+    }
+
+    #[test]
+    fn normalize_free_list() {
+        let mut v = OptVec::from_parts(vec![None, Some(1), None, None, Some(4)], vec![0, 3, 2]);
+        v.normalize_free_list();
+        assert_eq!(v.free, vec![3, 2, 0]);
+        assert_eq!(v.push(5), 0);
+        assert_eq!(v.push(6), 2);
+        assert_eq!(v.push(7), 3);
+    }
+
+    #[test]
+    fn peek_last() {
+        let mut v = OptVec::new();
+        assert_eq!(v.peek_last(), None);
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(3);
+
+        assert_eq!(v.peek_last(), Some(&2));
+        assert_eq!(v.pop(), Some(2));
+        assert_eq!(v.inner, vec![Some(0)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+        assert_eq!(v.peek_last(), Some(&0));
+        assert_eq!(v.pop(), Some(0));
+        assert_eq!(v.pop(), None);
+    }
+
+    #[test]
+    fn clone_with_capacity() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(2);
+
+        let w = v.clone_with_capacity(32);
+        assert_eq!(w, v.clone());
+        assert!(w.capacity() >= 32);
+        assert!(w.inner.capacity() >= 32);
+        assert_eq!(v.clone_with_capacity(0), v);
+    }
+
+    #[test]
+    fn is_free() {
+        use super::ReusePolicy;
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(2);
+        assert!(v.is_free(1));
+        assert!(v.is_free(2));
+        assert!(!v.is_free(0));
+        assert!(!v.is_free(4));
+
+        v.set_reuse_policy(ReusePolicy::LowestIndex);
+        assert!(v.is_free(1));
+        assert!(!v.is_free(3));
+        assert_eq!(v.allocate_slot(), 1);
+        assert!(!v.is_free(1));
+    }
+
+    #[test]
+    fn compact_keep_capacity() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(2);
+
+        assert_eq!(v.compact_keep_capacity(), vec![(1, 0), (3, 1), (4, 2)]);
+        assert_eq!(v.inner, vec![Some(1), Some(3), Some(4), None, None]);
+        assert_eq!(v.inner_len(), 5);
+        assert_eq!(v.free, vec![4, 3]);
+        assert_eq!(v.push(5), 3);
+    }
+
+    #[test]
+    fn values_at() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        assert_eq!(
+            v.values_at(&[3, 1, 9, 0, 3]).collect::<Vec<_>>(),
+            vec![Some(&3), None, None, Some(&0), Some(&3)]
+        );
+    }
+
+    #[test]
+    fn set_many() {
+        let mut v = OptVec::new();
+        for i in 0..3 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        assert_eq!(
+            v.set_many(vec![(0, 10), (1, 11), (4, 14), (0, 20)]),
+            vec![Some(0), None, None, Some(10)]
+        );
+        assert_eq!(v.inner, vec![Some(20), Some(11), Some(2), None, Some(14)]);
+        assert_eq!(v.free, vec![3]);
+    }
+
+    #[test]
+    fn retain_or_reset() {
+        let mut v = OptVec::new();
+        for i in 1..=5 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        v.retain_or_reset(|a| a % 2 == 1);
+        assert_eq!(v.inner, vec![Some(1), None, Some(3), Some(0), Some(5)]);
+        assert_eq!(v.len(), 4);
+        assert_eq!(v.free, vec![1]);
+    }
+
+    #[test]
+    fn index_set_operations() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let mut w = OptVec::new();
+        for a in ["a", "b", "c"] {
+            w.push(a);
+        }
+        w.remove(0);
+
+        assert_eq!(v.index_difference(&w), vec![0, 3, 4]);
+        assert_eq!(w.index_difference(&v), vec![1]);
+        assert_eq!(v.index_intersection(&w), vec![2]);
+        assert_eq!(w.index_intersection(&v), vec![2]);
+    }
+
+    #[test]
+    fn apply_remap() {
+        use super::RemapError;
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        assert_eq!(v.apply_remap(&[(0, 2), (2, 0), (3, 5)]), Ok(()));
+        assert_eq!(v.inner, vec![Some(2), None, Some(0), None, None, Some(3)]);
+        assert_eq!(v.free, vec![1, 4, 3]);
+
+        let w = v.clone();
+        assert_eq!(
+            v.apply_remap(&[(0, 1), (2, 1)]),
+            Err(RemapError::DuplicateTarget { index: 1 })
+        );
+        assert_eq!(
+            v.apply_remap(&[(0, 5)]),
+            Err(RemapError::TargetOccupied { index: 5 })
+        );
+        assert_eq!(
+            v.apply_remap(&[(1, 3)]),
+            Err(RemapError::SourceNotLive { index: 1 })
+        );
+        assert_eq!(
+            v.apply_remap(&[(0, 3), (0, 4)]),
+            Err(RemapError::DuplicateSource { index: 0 })
+        );
+        assert_eq!(v, w);
+
+        let remap = v.compact();
+        let inverse: Vec<_> = remap.iter().map(|&(old, new)| (new, old)).collect();
+        assert_eq!(v.apply_remap(&inverse), Ok(()));
+        assert_eq!(v.inner, w.inner);
+    }
+
+    #[test]
+    fn live_indices_sorted() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(4);
+        v.remove(0);
+        v.remove(2);
+
+        let indices = v.live_indices_sorted();
+        assert_eq!(indices, vec![1, 3, 5]);
+        assert_eq!(
+            indices,
+            (0..v.inner_len())
+                .filter(|&i| v.inner[i].is_some())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(indices.capacity(), 3);
+    }
+
+    #[test]
+    fn update() {
+        let mut v = OptVec::new();
+        v.push(1);
+        v.push(2);
+        v.remove(1);
+
+        assert!(v.update(0, |a| *a += 10));
+        assert_eq!(v.get(0), Some(&11));
+        assert!(!v.update(1, |_| panic!()));
+        assert!(!v.update(2, |_| panic!()));
+    }
+
+    #[test]
+    fn pop_n() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(5);
+        v.remove(3);
+
+        assert_eq!(v.pop_n(3), vec![4, 2, 1]);
+        assert_eq!(v.inner, vec![Some(0)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+        assert_eq!(v.pop_n(3), vec![0]);
+        assert_eq!(v.inner_len(), 0);
+    }
+
+    #[test]
+    fn push_returning_mut() {
+        let mut v = OptVec::new();
+        v.push(vec![0]);
+        v.push(vec![1]);
+        v.remove(0);
+
+        let (i, a) = v.push_returning_mut(vec![2]);
+        a.push(3);
+        assert_eq!(i, 0);
+        let (j, b) = v.push_returning_mut(vec![4]);
+        b.push(5);
+        assert_eq!(j, 2);
+
+        assert_eq!(v.get(0), Some(&vec![2, 3]));
+        assert_eq!(v.get(2), Some(&vec![4, 5]));
+    }
+
+    #[test]
+    fn retain_batched() {
+        use super::RetainProgress;
+
+        let mut v = OptVec::new();
+        for i in 0..10 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(2);
+        let mut w = v.clone();
+        w.retain(|a| a % 3 != 0);
+
+        let mut cursor = 0;
+        let mut calls = 0;
+        while let RetainProgress::Pending { cursor: next } =
+            v.retain_batched(cursor, 3, |a| a % 3 != 0)
+        {
+            assert!(v.free.iter().all(|&i| v.inner[i].is_none()));
+            cursor = next;
+            calls += 1;
+        }
+        assert_eq!(calls, 2);
+        assert_eq!(v, w);
+    }
+
+    #[test]
+    fn swap_inner() {
+        let mut v = OptVec::new();
+        for i in 0..3 {
+            v.push(i);
+        }
+        v.remove(1);
+
+        let mut w = OptVec::new();
+        w.push(10);
+        w.set_max_len(Some(4));
+        let ptr = v.inner.as_ptr();
+
+        v.swap_inner(&mut w);
+        assert_eq!(v.max_len(), None);
+        assert_eq!(w.max_len(), Some(4));
+        assert_eq!(v.inner, vec![Some(10)]);
+        assert_eq!(v.free, vec![] as Vec<usize>);
+        assert_eq!(w.inner, vec![Some(0), None, Some(2)]);
+        assert_eq!(w.free, vec![1]);
+        assert_eq!(w.inner.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn into_map() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i * 10);
+        }
+        v.remove(1);
+        v.remove(3);
+
+        let indices = v.live_indices_sorted();
+        let map = v.clone().into_map();
+        let mut keys: Vec<usize> = map.keys().copied().collect();
+        keys.sort_unstable();
+        assert_eq!(keys, indices);
+        for (i, a) in map {
+            assert_eq!(v.get(i), Some(&a));
+        }
+    }
+
+    #[test]
+    fn from_map() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i * 10);
+        }
+        v.remove(1);
+        v.remove(3);
+
+        let w = OptVec::from_map(v.clone().into_map());
+        assert_eq!(w.inner, v.inner);
+        assert_eq!(w.free, vec![3, 1]);
+        assert_eq!(OptVec::<i32>::from_map(Default::default()), OptVec::new());
+    }
+
+    #[test]
+    fn remove_all_eq() {
+        let mut v = OptVec::new();
+        for a in ["a", "b", "a", "c", "a"] {
+            v.push(a);
+        }
+
+        assert_eq!(v.remove_all_eq(&"a"), 3);
+        assert_eq!(v.inner, vec![None, Some("b"), None, Some("c"), None]);
+        assert_eq!(v.free, vec![0, 2, 4]);
+        assert_eq!(v.remove_all_eq(&"a"), 0);
+    }
+
+    #[test]
+    fn get_or_default() {
+        let mut v = OptVec::new();
+        v.push(1.5);
+        v.push(2.5);
+        v.remove(1);
+
+        assert_eq!(v.get_or_default(0), 1.5);
+        assert_eq!(v.get_or_default(1), 0.0);
+        assert_eq!(v.get_or_default(9), 0.0);
+    }
+
+    #[test]
+    fn insert_many() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i.to_string());
+        }
+        v.remove(0);
+        v.remove(2);
+
+        let values = (4..8).map(|i| i.to_string());
+        assert_eq!(v.insert_many(values), vec![2, 0, 4, 5]);
+        assert_eq!(v.get(0).map(String::as_str), Some("5"));
+        assert_eq!(v.get(5).map(String::as_str), Some("7"));
+        assert!(v.is_dense());
+    }
+
+    #[test]
+    fn auto_compact() {
+        use std::sync::{Arc, Mutex};
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        let remaps = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&remaps);
+        v.set_auto_compact(Some(0.5));
+        v.set_compact_callback(move |remap| sink.lock().unwrap().push(remap.to_vec()));
+
+        v.remove(0);
+        v.remove(2);
+        assert_eq!(v.inner_len(), 4);
+        assert!(remaps.lock().unwrap().is_empty());
+
+        v.remove(1);
+        assert_eq!(v.inner, vec![Some(3)]);
+        assert_eq!(*remaps.lock().unwrap(), vec![vec![(3, 0)]]);
+
+        let mut w = v.clone();
+        assert_eq!(w, v);
+        w.push(4);
+        w.push(5);
+        w.remove(0);
+        w.remove(1);
+        assert_eq!(w.inner, vec![None, None, Some(5)]);
+        let mut u = OptVec::new();
+        v.clone_into(&mut u);
+        assert_eq!(u.auto_compact, None);
+        v.set_auto_compact(None);
+        v.push(4);
+        v.remove(0);
+        assert_eq!(v.inner, vec![None, Some(4)]);
+        assert_eq!(remaps.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn auto_compact_pop() {
+        let mut v = OptVec::new();
+        for i in 0..5 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(1);
+        v.set_auto_compact(Some(0.4));
+        v.pop();
+        assert_eq!(v.inner, vec![Some(2), Some(3)]);
+
+        v.set_auto_compact(Some(0.0));
+        for i in 0..5 {
+            v.push(i);
+        }
+        assert_eq!(v.inner_len(), 7);
+    }
+
+    #[test]
+    #[should_panic]
+    fn auto_compact_nan() {
+        let mut v: OptVec<i32> = OptVec::new();
+        v.set_auto_compact(Some(f64::NAN));
+    }
+
+    #[test]
+    #[should_panic]
+    fn auto_compact_negative() {
+        let mut v: OptVec<i32> = OptVec::new();
+        v.set_auto_compact(Some(-1.0));
+    }
+
+    #[test]
+    fn iter_rev() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(3);
+        v.remove(5);
+
+        let mut forward: Vec<_> = v.iter_from(0).collect();
+        forward.reverse();
+        assert_eq!(v.iter_rev().collect::<Vec<_>>(), forward);
+        assert_eq!(forward, vec![(4, &4), (2, &2), (1, &1)]);
+    }
+
+    #[test]
+    fn shift_indices() {
+        let mut v = OptVec::new();
+        for i in 0..3 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.shift_indices(2);
+
+        assert_eq!(v.inner, vec![None, None, None, Some(1), Some(2)]);
+        assert_eq!(v.free, vec![2, 1, 0]);
+        assert_eq!(v.push(5), 0);
+    }
+
+    #[test]
+    fn split_off_where() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(4);
+        let w = v.split_off_where(|&a| a % 2 == 1);
+
+        assert_eq!(v.inner, vec![Some(0), None, Some(2), None, None, None]);
+        assert_eq!(w.inner, vec![None, Some(1), None, Some(3), None, Some(5)]);
+        assert_eq!(w.free, vec![4, 2, 0]);
+        assert_eq!(w[3], 3);
+    }
+
+    #[test]
+    fn take_churn_stats() {
+        use super::ChurnStats;
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(1);
+        assert!(v.remove_if_present(2));
+        assert!(!v.remove_if_present(2));
+        v.push(5);
+
+        assert_eq!(
+            v.take_churn_stats(),
+            ChurnStats {
+                pushed: 5,
+                removed: 2
+            }
+        );
+        assert_eq!(v.take_churn_stats(), ChurnStats::default());
+
+        let mut v = OptVec::new();
+        v.push(0);
+        v.push(1);
+        v.pop();
+        v.pop_front();
+        assert_eq!(
+            v.take_churn_stats(),
+            ChurnStats {
+                pushed: 2,
+                removed: 2
+            }
+        );
+
+        v.insert_at(3, 0);
+        v.insert_at(3, 1);
+        v.extend([(0, 2), (1, 3)]);
+        v.retain(|&a| a != 2);
+        v.compact();
+        v.drain_range(0..1).for_each(drop);
+        assert_eq!(
+            v.take_churn_stats(),
+            ChurnStats {
+                pushed: 3,
+                removed: 2
+            }
+        );
+
+        v.push(4);
+        assert_eq!(v.clone().take_churn_stats(), ChurnStats::default());
+        assert_eq!(v.take_churn_stats().pushed, 1);
+
+        for i in 5..9 {
+            v.push(i);
+        }
+        v.take_churn_stats();
+        let tail = v.split_at_ordinal(2);
+        assert_eq!(tail.len(), 4);
+        assert_eq!(
+            v.take_churn_stats(),
+            ChurnStats {
+                pushed: 0,
+                removed: 4
+            }
+        );
+    }
+
+    #[test]
+    fn slice_live() {
+        let mut v = OptVec::new();
+        for i in 0..7 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(2);
+        v.remove(4);
+
+        assert_eq!(v.slice_live(1..5), vec![&1, &3]);
+        assert_eq!(v.slice_live(5..10), vec![&5, &6]);
+        assert!(v.slice_live(8..10).is_empty());
+    }
+
+    #[test]
+    fn push_bounded() {
+        let mut v = OptVec::new();
+        v.set_max_len(Some(2));
+        assert_eq!(v.push_bounded(0), Ok(0));
+        assert_eq!(v.push_bounded(1), Ok(1));
+        assert_eq!(v.push_bounded(2), Err(2));
+        assert_eq!(v.inner, vec![Some(0), Some(1)]);
+
+        v.remove(0);
+        assert_eq!(v.push_bounded(3), Ok(0));
+        assert_eq!(v.push_bounded(4), Err(4));
+
+        v.set_max_len(None);
+        assert_eq!(v.push_bounded(4), Ok(2));
+
+        let mut v = OptVec::from_parts(vec![Some(0)], vec![0, 0, 0]);
+        v.set_max_len(Some(2));
+        assert!(v.push_bounded(1).is_ok());
+    }
+
+    #[test]
+    fn retain_reporting() {
+        let mut v = OptVec::new();
+        for i in 0..6 {
+            v.push(i * 10);
+        }
+        v.remove(1);
+        let before = v.live_indices_sorted();
+        let (kept, removed) = v.retain_reporting(|i, &a| i != 0 && a != 40);
+
+        assert_eq!(kept, vec![2, 3, 5]);
+        assert_eq!(removed, vec![0, 4]);
+        assert!(kept.iter().all(|i| !removed.contains(i)));
+        let mut all = [kept.clone(), removed].concat();
+        all.sort_unstable();
+        assert_eq!(all, before);
+        assert_eq!(v.live_indices_sorted(), kept);
+        assert_eq!(v[5], 50);
+    }
+
+    #[test]
+    fn drain_compact_into() {
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        let mut dest = OptVec::new();
+        for i in 10..14 {
+            dest.push(i);
+        }
+        dest.remove(0);
+        dest.remove(2);
+        v.drain_compact_into(&mut dest);
+
+        assert_eq!(
+            dest.inner,
+            vec![Some(2), Some(11), Some(0), Some(13), Some(3)]
+        );
+        assert!(dest.free.is_empty());
+        assert!(v.inner.is_empty());
+        assert!(v.free.is_empty());
+    }
+
+    #[test]
+    fn compact_front() {
+        let mut v = OptVec::new();
+        for i in 0..8 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(1);
+        v.remove(3);
+        v.remove(6);
+
+        assert_eq!(v.compact_front(2), vec![(7, 0), (5, 1)]);
+        assert_eq!(v.inner, vec![Some(7), Some(5), Some(2), None, Some(4)]);
+        assert_eq!(v.free, vec![3]);
+
+        assert_eq!(v.compact_front(5), vec![(4, 3)]);
+        assert_eq!(v.inner, vec![Some(7), Some(5), Some(2), Some(4)]);
+        assert!(v.free.is_empty());
+        assert!(v.compact_front(5).is_empty());
+
+        v.remove(3);
+        v.remove(2);
+        assert!(v.compact_front(0).is_empty());
+        assert_eq!(v.inner_len(), 4);
+    }
+
+    #[test]
+    fn get_cloned() {
+        let mut v = OptVec::new();
+        v.push(String::from("a"));
+        v.push(String::from("b"));
+        v.remove(1);
+
+        let a = v.get_cloned(0);
+        v[0].push('c');
+        v.remove(0);
+        assert_eq!(a.as_deref(), Some("a"));
+        assert_eq!(v.get_cloned(0), None);
+        assert_eq!(v.get_cloned(5), None);
+    }
+
+    #[test]
+    fn reserve_like() {
+        let mut a = OptVec::with_capacity(16);
+        for i in 0..10 {
+            a.push(i);
+        }
+        a.reserve_free(8);
+        let mut b = OptVec::new();
+        b.push(0);
+        b.reserve_like(&a);
+
+        assert!(b.inner.capacity() >= a.inner.capacity());
+        assert!(b.free.capacity() >= a.free.capacity());
+    }
+
+    #[test]
+    fn validate() {
+        use super::{Inconsistency, ReusePolicy};
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.allocate_slot();
+        v.remove(2);
+        assert_eq!(v.validate(), Ok(()));
+
+        let v = OptVec::from_parts(vec![Some(0), None, None, Some(3)], vec![5, 0, 1, 1]);
+        assert_eq!(
+            v.validate(),
+            Err(vec![
+                Inconsistency::FreeOutOfBounds { index: 5 },
+                Inconsistency::FreeOccupied { index: 0 },
+                Inconsistency::DuplicateFree { index: 1 },
+                Inconsistency::MissingFree { index: 2 },
+            ])
+        );
+
+        let mut v = OptVec::from_parts(vec![None, Some(1), None], vec![0, 2]);
+        v.reserved.push(1);
+        v.reserved.push(2);
+        v.policy = ReusePolicy::LowestIndex;
+        assert_eq!(
+            v.validate(),
+            Err(vec![
+                Inconsistency::ReservedInvalid { index: 1 },
+                Inconsistency::ReservedInvalid { index: 2 },
+                Inconsistency::FreeUnsorted,
+            ])
+        );
+    }
+
+    #[test]
+    fn repair() {
+        let mut v = OptVec::from_parts(vec![Some(0), None, None, Some(3), None], vec![7, 0, 1, 1]);
+        v.reserved.extend([2, 2, 3]);
+        assert!(v.validate().is_err());
+
+        v.repair();
+        assert_eq!(v.validate(), Ok(()));
+        assert_eq!(v.free, vec![4, 1]);
+        assert_eq!(v.reserved, vec![2]);
+
+        assert_eq!(v.push(5), 1);
+        assert_eq!(v.push(6), 4);
+        assert_eq!(v.push(7), 5);
+        assert_eq!(v.remove(0), Some(0));
+        assert_eq!(v.validate(), Ok(()));
+    }
+
+    #[test]
+    fn pop_trailing_free() {
+        use super::ReusePolicy;
+
+        let mut v = OptVec::new();
+        v.set_reuse_policy(ReusePolicy::LowestIndex);
+        for i in 0..6 {
+            v.push(i);
+        }
+        v.remove(0);
+        v.remove(3);
+        v.remove(4);
+
+        assert_eq!(v.pop(), Some(5));
+        assert_eq!(v.inner, vec![None, Some(1), Some(2)]);
+        assert_eq!(v.free, vec![0]);
+        assert_eq!(v.pop(), Some(2));
+        assert_eq!(v.inner, vec![None, Some(1)]);
+        assert_eq!(v.free, vec![0]);
+
+        let mut v = OptVec::new();
+        for i in 0..4 {
+            v.push(i);
+        }
+        v.remove(1);
+        v.remove(2);
+        assert_eq!(v.allocate_slot(), 2);
+        assert_eq!(v.pop(), Some(3));
+        assert_eq!(v.inner, vec![Some(0), None, None]);
+        assert_eq!(v.free, vec![1]);
+        assert_eq!(v.pop(), Some(0));
+        assert_eq!(v.inner, vec![None, None, None]);
+        assert_eq!(v.free, vec![1, 0]);
     }
 }
@@ -0,0 +1,71 @@
+use crate::OptVec;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use core::fmt;
+use core::marker::PhantomData;
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+/// Serializes an [`OptVec<T>`] as a sequence of [`Option<T>`], keeping the free space.
+/// The index of each element is preserved, and it can be deserialized back into an [`OptVec<T>`].
+///
+/// This is the format used by the [`Serialize`] implementation of [`OptVec<T>`].
+pub struct SparseFormat<'a, T>(pub &'a OptVec<T>);
+
+/// Serializes an [`OptVec<T>`] as a sequence of `T`, skipping the free space.
+/// The output is smaller, but the index of each element is lost.
+pub struct CompactFormat<'a, T>(pub &'a OptVec<T>);
+
+impl<'a, T: Serialize> Serialize for SparseFormat<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.inner.len()))?;
+        for a in &self.0.inner {
+            seq.serialize_element(a)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'a, T: Serialize> Serialize for CompactFormat<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for a in self.0.inner.iter().flatten() {
+            seq.serialize_element(a)?;
+        }
+        seq.end()
+    }
+}
+
+impl<T: Serialize> Serialize for OptVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SparseFormat(self).serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for OptVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SparseVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: Deserialize<'de>> Visitor<'de> for SparseVisitor<T> {
+            type Value = OptVec<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a sequence of optional values")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut inner = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                while let Some(a) = seq.next_element()? {
+                    inner.push(a);
+                }
+                let mut v = OptVec::from_parts(inner, Vec::new());
+                v.rebuild_free_list();
+                Ok(v)
+            }
+        }
+
+        deserializer.deserialize_seq(SparseVisitor(PhantomData))
+    }
+}
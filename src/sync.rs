@@ -0,0 +1,80 @@
+use crate::OptVec;
+
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// An [`OptVec<T>`] behind a [`RwLock`], so that it can be shared between threads.
+///
+/// Every method takes the lock for the duration of the call only.
+/// To keep the lock over several operations, use [`SyncOptVec::read()`] or [`SyncOptVec::write()`];
+/// other threads are blocked until the returned guard is dropped.
+///
+/// ## Panic
+/// Every method panics if the lock is poisoned, i.e. another thread panicked while holding it.
+#[derive(Debug, Default)]
+pub struct SyncOptVec<T> {
+    inner: RwLock<OptVec<T>>,
+}
+
+impl<T> SyncOptVec<T> {
+    /// Constructs a new, empty `SyncOptVec<T>`
+    pub fn new() -> Self {
+        Self::from(OptVec::new())
+    }
+
+    /// Locks the vector for reading and returns a guard to it.
+    pub fn read(&self) -> RwLockReadGuard<'_, OptVec<T>> {
+        self.inner.read().unwrap()
+    }
+
+    /// Locks the vector for writing and returns a guard to it.
+    pub fn write(&self) -> RwLockWriteGuard<'_, OptVec<T>> {
+        self.inner.write().unwrap()
+    }
+
+    /// Returns a clone of the element at position `index`,
+    /// or [`None`] if it is out of bounds or free space.
+    pub fn get(&self, index: usize) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.read().get(index).cloned()
+    }
+
+    /// Same as [`OptVec::len()`]
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Same as [`OptVec::is_empty()`]
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Same as [`OptVec::push()`]
+    pub fn push(&self, value: T) -> usize {
+        self.write().push(value)
+    }
+
+    /// Same as [`OptVec::remove()`], but returns [`None`] if `index` is out of bounds.
+    pub fn remove(&self, index: usize) -> Option<T> {
+        let mut v = self.write();
+        if index < v.inner_len() {
+            v.remove(index)
+        } else {
+            None
+        }
+    }
+
+    /// Consumes the lock and returns the underlying [`OptVec<T>`]
+    pub fn into_inner(self) -> OptVec<T> {
+        self.inner.into_inner().unwrap()
+    }
+}
+
+impl<T> From<OptVec<T>> for SyncOptVec<T> {
+    fn from(v: OptVec<T>) -> Self {
+        Self {
+            inner: RwLock::new(v),
+        }
+    }
+}